@@ -18,7 +18,7 @@ static TEST_LOG_PATH: &str = "./test-logs";
 async fn put_one_million() {
     let path = PathBuf::from(format!("{TEST_LOG_PATH}/{:?}", Uuid::new_v4()));
     let _ = create_dir_all(&path).await;
-    let mut db = BaumDb::new(&path, 100_000).await;
+    let db = BaumDb::new(&path, 100_000).await.unwrap();
     for i in 0..1_000_000 {
         db.put(i.to_string(), "MyValue".to_string()).await.unwrap();
     }
@@ -27,12 +27,12 @@ async fn put_one_million() {
 async fn put_and_get_one_million() {
     let path = PathBuf::from(format!("{TEST_LOG_PATH}/{:?}", Uuid::new_v4()));
     let _ = create_dir_all(&path).await;
-    let mut db = BaumDb::new(&path, 100_000).await;
+    let db = BaumDb::new(&path, 100_000).await.unwrap();
     for i in 0..1_000_000 {
         db.put(i.to_string(), "MyValue".to_string()).await.unwrap();
     }
     for i in 0..1_000_000 {
-        db.get(&i.to_string()).await.unwrap();
+        db.get(i.to_string()).await.unwrap();
     }
 }
 
@@ -53,7 +53,7 @@ async fn random_access() {
         .collect();
     let path = PathBuf::from(format!("{TEST_LOG_PATH}/{:?}", Uuid::new_v4()));
     let _ = create_dir_all(&path).await;
-    let mut db = BaumDb::new(&path, 100).await;
+    let db = BaumDb::new(&path, 100).await.unwrap();
 
     for _ in 0..10_000 {
         let key_idx = rng.gen_range(0..keys.len());
@@ -0,0 +1,244 @@
+use std::ops::RangeBounds;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tokio::runtime::Builder;
+use tokio::runtime::Runtime;
+
+use crate::db::BaumDb;
+use crate::db::DB;
+use crate::error::Error;
+use crate::error::Result;
+use crate::iterator::DbIterator;
+use crate::merge_operator::MergeOperator;
+use crate::options::Options;
+use crate::write_batch::WriteBatch;
+
+/// The number of threads running the background flush and compaction tasks and the blocking calls.
+const WORKER_THREADS: usize = 2;
+
+/// A blocking wrapper around [`BaumDb`] for use from synchronous code.
+///
+/// It owns the Tokio runtime that runs the background tasks of the database, so no runtime needs
+/// to be active when it is opened. Clones share the same database and runtime.
+///
+/// # Panics
+///
+/// All methods panic if called from within an asynchronous context, use [`BaumDb`] there instead.
+#[derive(Debug, Clone)]
+pub struct BaumDbSync {
+    db: BaumDb,
+    runtime: Arc<Runtime>,
+}
+
+impl BaumDbSync {
+    /// Like [`BaumDb::open`], but blocking.
+    pub fn open<P>(
+        sst_dir_path: P,
+        max_memtable_size: usize,
+    ) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        P: Into<PathBuf>,
+    {
+        let runtime = new_runtime()?;
+        let db = runtime.block_on(BaumDb::open(sst_dir_path, max_memtable_size))?;
+        Ok(Self {
+            db,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// Like [`BaumDb::open_with_merge_operator`], but blocking.
+    pub fn open_with_merge_operator<P>(
+        sst_dir_path: P,
+        max_memtable_size: usize,
+        merge_operator: Arc<dyn MergeOperator>,
+    ) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        P: Into<PathBuf>,
+    {
+        let runtime = new_runtime()?;
+        let db = runtime.block_on(BaumDb::open_with_merge_operator(
+            sst_dir_path,
+            max_memtable_size,
+            merge_operator,
+        ))?;
+        Ok(Self {
+            db,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// Like [`BaumDb::open_with_options`], but blocking.
+    pub fn open_with_options<P>(
+        sst_dir_path: P,
+        options: Options,
+    ) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        P: Into<PathBuf>,
+    {
+        let runtime = new_runtime()?;
+        let db = runtime.block_on(BaumDb::open_with_options(sst_dir_path, options))?;
+        Ok(Self {
+            db,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// See [`DB::get`].
+    pub fn get<K>(
+        &self,
+        key: K,
+    ) -> Result<Option<Bytes>>
+    where
+        K: AsRef<[u8]> + Send + Sync,
+    {
+        self.runtime.block_on(self.db.get(key))
+    }
+
+    /// See [`DB::get_string`].
+    pub fn get_string<K>(
+        &self,
+        key: K,
+    ) -> Result<Option<String>>
+    where
+        K: AsRef<[u8]> + Send + Sync,
+    {
+        self.runtime.block_on(self.db.get_string(key))
+    }
+
+    /// See [`DB::put`].
+    pub fn put<K, V>(
+        &self,
+        key: K,
+        value: V,
+    ) -> Result<()>
+    where
+        K: Into<Bytes> + Send,
+        V: Into<Bytes> + Send,
+    {
+        self.runtime.block_on(self.db.put(key, value))
+    }
+
+    /// See [`DB::delete`].
+    pub fn delete<K>(
+        &self,
+        key: K,
+    ) -> Result<()>
+    where
+        K: AsRef<[u8]> + Send + Sync,
+    {
+        self.runtime.block_on(self.db.delete(key))
+    }
+
+    /// See [`DB::delete_range`].
+    pub fn delete_range<K>(
+        &self,
+        start: K,
+        end: K,
+    ) -> Result<()>
+    where
+        K: AsRef<[u8]> + Send + Sync,
+    {
+        self.runtime.block_on(self.db.delete_range(start, end))
+    }
+
+    /// See [`DB::write`].
+    pub fn write(
+        &self,
+        batch: WriteBatch,
+    ) -> Result<()> {
+        self.runtime.block_on(self.db.write(batch))
+    }
+
+    /// See [`DB::scan`].
+    /// The returned iterator reads the data blocks as it reaches them, blocking the calling thread.
+    pub fn scan<K, R>(
+        &self,
+        range: R,
+    ) -> Result<DbIteratorSync>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K> + Send,
+    {
+        let iterator = self.runtime.block_on(self.db.scan(range))?;
+        Ok(DbIteratorSync {
+            iterator,
+            runtime: self.runtime.clone(),
+        })
+    }
+
+    /// See [`BaumDb::close`].
+    /// Should be called before the last clone is dropped, which shuts down the runtime and with
+    /// it any flush or compaction still running.
+    pub fn close(&self) -> Result<()> {
+        self.runtime.block_on(self.db.close())
+    }
+}
+
+/// A blocking wrapper around [`DbIterator`], returned by [`BaumDbSync::scan`].
+#[derive(Debug)]
+pub struct DbIteratorSync {
+    iterator: DbIterator,
+    runtime: Arc<Runtime>,
+}
+
+impl DbIteratorSync {
+    /// See [`DbIterator::error`].
+    pub fn error(&self) -> Option<&Error> {
+        self.iterator.error()
+    }
+
+    /// Like [`DbIterator::prev`], but blocking.
+    pub fn prev(&mut self) -> Option<(Bytes, Bytes)> {
+        self.runtime.block_on(self.iterator.prev())
+    }
+
+    /// See [`DbIterator::seek`].
+    pub fn seek(
+        &mut self,
+        key: impl AsRef<[u8]>,
+    ) {
+        self.iterator.seek(key);
+    }
+
+    /// See [`DbIterator::seek_for_prev`].
+    pub fn seek_for_prev(
+        &mut self,
+        key: impl AsRef<[u8]>,
+    ) {
+        self.iterator.seek_for_prev(key);
+    }
+
+    /// See [`DbIterator::seek_to_first`].
+    pub fn seek_to_first(&mut self) {
+        self.iterator.seek_to_first();
+    }
+
+    /// See [`DbIterator::seek_to_last`].
+    pub fn seek_to_last(&mut self) {
+        self.iterator.seek_to_last();
+    }
+}
+
+impl Iterator for DbIteratorSync {
+    type Item = (Bytes, Bytes);
+
+    /// Like [`DbIterator::next`], but blocking.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.runtime.block_on(self.iterator.next())
+    }
+}
+
+fn new_runtime() -> Result<Runtime> {
+    Ok(Builder::new_multi_thread()
+        .worker_threads(WORKER_THREADS)
+        .thread_name("baumdb")
+        .enable_all()
+        .build()?)
+}
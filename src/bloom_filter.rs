@@ -1,33 +1,39 @@
-use std::collections::hash_map::DefaultHasher;
-use std::hash::Hasher;
 use std::path::Path;
 use std::path::PathBuf;
 
-use anyhow::anyhow;
-use anyhow::Error;
-use anyhow::Result;
 use async_trait::async_trait;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
 
+use crate::checksum::checksum;
+use crate::checksum::strip_checksum;
+use crate::error::Error;
+use crate::error::Result;
 use crate::file_handling::DataHandling;
 
+/// Marks bloom filter files whose slots are set by [`BloomHasher`]. Every slot is either 0 or 1, so
+/// the files written before the marker existed, which were hashed with the unstable
+/// `DefaultHasher` of the standard library, never start with it.
+const CRC32C_HASHED: u8 = 0xc3;
+
 pub(crate) trait BloomFilter {
     fn add_key(
         &mut self,
-        key: &str,
+        key: &[u8],
     );
 
     fn may_contain_key(
         &self,
-        key: &str,
+        key: &[u8],
     ) -> bool;
 }
 
 #[derive(Debug)]
 pub(crate) struct DefaultBloomFilter {
     filter: Vec<u8>,
-    hasher: BloomHasher,
+    /// `None` for filters read from files without the [`CRC32C_HASHED`] marker, which may contain
+    /// any key.
+    hasher: Option<BloomHasher>,
 }
 
 impl DefaultBloomFilter {
@@ -37,7 +43,7 @@ impl DefaultBloomFilter {
     ) -> Self {
         Self {
             filter: vec![0; size],
-            hasher: BloomHasher { size, n_hashes },
+            hasher: Some(BloomHasher { size, n_hashes }),
         }
     }
 }
@@ -51,19 +57,26 @@ impl DataHandling for DefaultBloomFilter {
         P: Into<PathBuf>,
         P: Send,
     {
+        let path = path.as_ref();
         let mut bloom_filter_file = File::open(path).await?;
         let mut bloom_filter_bytes = Vec::<u8>::new();
         bloom_filter_file
             .read_to_end(&mut bloom_filter_bytes)
             .await?;
+        strip_checksum(&mut bloom_filter_bytes, path)?;
         DefaultBloomFilter::try_from(bloom_filter_bytes)
     }
 }
 
 impl From<DefaultBloomFilter> for Vec<u8> {
-    fn from(mut value: DefaultBloomFilter) -> Self {
-        value.filter.push(value.hasher.n_hashes);
-        value.filter
+    fn from(value: DefaultBloomFilter) -> Self {
+        // Unwrap is OK as filters without a hasher are only ever read from files.
+        let n_hashes = value.hasher.unwrap().n_hashes;
+        let mut bytes = Vec::with_capacity(value.filter.len() + 2);
+        bytes.push(CRC32C_HASHED);
+        bytes.extend(value.filter);
+        bytes.push(n_hashes);
+        bytes
     }
 }
 
@@ -72,29 +85,33 @@ impl TryFrom<Vec<u8>> for DefaultBloomFilter {
 
     fn try_from(mut bytes: Vec<u8>) -> Result<Self> {
         if bytes.len() < 2 {
-            return Err(anyhow!("Bytes for bloom filter construction too short."));
+            return Err(Error::InvalidData(
+                "Bytes for bloom filter construction too short.".to_string(),
+            ));
         }
         let n_hashes = bytes.remove(bytes.len() - 1);
+        if bytes[0] != CRC32C_HASHED {
+            return Ok(Self {
+                filter: bytes,
+                hasher: None,
+            });
+        }
+        bytes.remove(0);
         let size = bytes.len();
         Ok(Self {
             filter: bytes,
-            hasher: BloomHasher { size, n_hashes },
+            hasher: Some(BloomHasher { size, n_hashes }),
         })
     }
 }
 
-impl Default for DefaultBloomFilter {
-    fn default() -> Self {
-        Self::new(65536, 5)
-    }
-}
-
 impl BloomFilter for DefaultBloomFilter {
     fn add_key(
         &mut self,
-        key: &str,
+        key: &[u8],
     ) {
-        let bloom_filter_indices = self.hasher.hash_key(key);
+        // Unwrap is OK as filters without a hasher are only ever read from files.
+        let bloom_filter_indices = self.hasher.as_ref().unwrap().hash_key(key);
         for idx in bloom_filter_indices {
             self.filter[idx] = 1;
         }
@@ -102,9 +119,12 @@ impl BloomFilter for DefaultBloomFilter {
 
     fn may_contain_key(
         &self,
-        key: &str,
+        key: &[u8],
     ) -> bool {
-        let bloom_filter_indices = self.hasher.hash_key(key);
+        let Some(hasher) = &self.hasher else {
+            return true;
+        };
+        let bloom_filter_indices = hasher.hash_key(key);
         bloom_filter_indices
             .into_iter()
             .all(|idx| self.filter[idx] == 1)
@@ -114,10 +134,13 @@ impl BloomFilter for DefaultBloomFilter {
 trait BloomHash {
     fn hash_key(
         &self,
-        key: &str,
+        key: &[u8],
     ) -> Vec<usize>;
 }
 
+/// Derives the slots of a key from its crc32c by double hashing: the `i`-th slot is
+/// `crc + i * delta`, where `delta` is the crc rotated by 17 bits. Unlike the hashers of the standard
+/// library, crc32c is fixed, so filters written by one build are read correctly by every other.
 #[derive(Debug)]
 struct BloomHasher {
     size: usize,
@@ -127,15 +150,43 @@ struct BloomHasher {
 impl BloomHash for BloomHasher {
     fn hash_key(
         &self,
-        key: &str,
+        key: &[u8],
     ) -> Vec<usize> {
-        let mut indices = vec![0; self.n_hashes as usize];
-        let mut hasher = DefaultHasher::new();
-        hasher.write(key.as_bytes());
-        for idx in 0..self.n_hashes {
-            hasher.write_u8(idx);
-            indices.push(hasher.finish() as usize % self.size)
+        let mut hash = checksum(key);
+        let delta = hash.rotate_left(17);
+        let mut indices = Vec::with_capacity(self.n_hashes as usize);
+        for _ in 0..self.n_hashes {
+            indices.push(hash as usize % self.size);
+            hash = hash.wrapping_add(delta);
         }
         indices
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_are_derived_from_the_crc32c_of_the_key() {
+        let hasher = BloomHasher {
+            size: 1000,
+            n_hashes: 3,
+        };
+        // crc32c("key") is 0x40a4_756d, rotated by 17 bits 0xeada_8148.
+        assert_eq!(hasher.hash_key(b"key"), vec![789, 981, 173]);
+    }
+
+    #[test]
+    fn filters_from_older_files_may_contain_any_key() {
+        let mut filter = DefaultBloomFilter::new(64, 3);
+        filter.add_key(b"key");
+        let bytes: Vec<u8> = filter.into();
+        let filter = DefaultBloomFilter::try_from(bytes.clone()).unwrap();
+        assert!(filter.may_contain_key(b"key"));
+        assert!(!filter.may_contain_key(b"other key"));
+
+        let legacy_filter = DefaultBloomFilter::try_from(bytes[1..].to_vec()).unwrap();
+        assert!(legacy_filter.may_contain_key(b"other key"));
+    }
+}
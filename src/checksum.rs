@@ -0,0 +1,45 @@
+use std::path::Path;
+
+use crate::error::CorruptionError;
+use crate::error::Result;
+
+/// The number of bytes of a checksum.
+pub(crate) const CHECKSUM_LEN: usize = 4;
+
+pub(crate) fn checksum(bytes: &[u8]) -> u32 {
+    crc32c::crc32c(bytes)
+}
+
+/// Verifies that the bytes found at `offset` in the file at `path` match the expected checksum.
+pub(crate) fn verify_checksum(
+    bytes: &[u8],
+    expected_checksum: u32,
+    path: &Path,
+    offset: u64,
+) -> Result<()> {
+    if checksum(bytes) != expected_checksum {
+        return Err(CorruptionError::new(path, offset).into());
+    }
+    Ok(())
+}
+
+/// Appends the checksum of the whole file content as a trailer.
+pub(crate) fn append_checksum(bytes: &mut Vec<u8>) {
+    let checksum = checksum(bytes);
+    bytes.extend(checksum.to_be_bytes());
+}
+
+/// Verifies and removes the checksum trailer added by `append_checksum` from the content of the
+/// file at `path`.
+pub(crate) fn strip_checksum(
+    bytes: &mut Vec<u8>,
+    path: &Path,
+) -> Result<()> {
+    let Some(content_len) = bytes.len().checked_sub(CHECKSUM_LEN) else {
+        return Err(CorruptionError::new(path, 0).into());
+    };
+    // Unwrap is OK as the trailer has exactly `CHECKSUM_LEN` bytes
+    let expected_checksum = u32::from_be_bytes(bytes[content_len..].try_into().unwrap());
+    bytes.truncate(content_len);
+    verify_checksum(bytes, expected_checksum, path, 0)
+}
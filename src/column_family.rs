@@ -0,0 +1,786 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::io::Cursor;
+use std::io::SeekFrom;
+use std::mem;
+use std::ops::Bound;
+use std::ops::RangeBounds;
+use std::panic;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::sync::RwLockReadGuard;
+use std::sync::RwLockWriteGuard;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use tokio::fs::remove_file;
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncSeekExt;
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+
+use crate::bloom_filter::BloomFilter;
+use crate::bloom_filter::DefaultBloomFilter;
+use crate::checksum::strip_checksum;
+use crate::deserialization::decode_block;
+use crate::deserialization::read_key_offset;
+use crate::deserialization::read_key_value;
+use crate::deserialization::KeyOffset;
+use crate::deserialization::KeyValue;
+use crate::error::Error;
+use crate::error::Result;
+use crate::file_handling::DataHandling;
+use crate::file_handling::FileBundles;
+use crate::file_handling::FileHandling;
+use crate::file_handling::SstFileBundle;
+use crate::file_handling::SstFileHandler;
+use crate::iterator::is_before_start;
+use crate::iterator::is_past_end;
+use crate::iterator::Gap;
+use crate::iterator::KeyBounds;
+use crate::iterator::SortedRun;
+use crate::memtable::extend_with_older_versions;
+use crate::memtable::is_complete;
+use crate::memtable::versions_to_read;
+use crate::memtable::MemTableGet;
+use crate::memtable::MemTableRun;
+use crate::memtable::MemTableWrite;
+use crate::memtable::SequenceNumber;
+use crate::memtable::SharedMemTable;
+use crate::memtable::Versions;
+use crate::memtable::WriteEntry;
+use crate::merge_operator::MergeOperator;
+use crate::options::Options;
+use crate::range_tombstone::apply_range_tombstones;
+use crate::range_tombstone::read_range_tombstones;
+use crate::range_tombstone::RangeTombstone;
+use crate::wal::Wal;
+
+/// The name of the column family every database has, which is used by the methods that do not
+/// take a [`ColumnFamilyHandle`].
+pub(crate) const DEFAULT_COLUMN_FAMILY_NAME: &str = "default";
+
+/// Identifies a column family of a [`BaumDb`](crate::BaumDb).
+///
+/// Column families are separate keyspaces of the same database. Each has its own memtables and
+/// SST bundles, but they share the database directory, the background flush and compaction tasks,
+/// and the sequence numbers, so that a snapshot covers all of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnFamilyHandle {
+    // The position of the column family in the database
+    id: usize,
+    name: Arc<str>,
+}
+
+impl ColumnFamilyHandle {
+    pub(crate) fn new(
+        id: usize,
+        name: &str,
+    ) -> Self {
+        Self {
+            id,
+            name: name.into(),
+        }
+    }
+
+    pub(crate) fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Checks that the name can be used as the directory name of a column family.
+pub(crate) fn validate_column_family_name(name: &str) -> Result<()> {
+    let is_valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if is_valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidArgument(format!(
+            "Invalid column family name: {name:?}"
+        )))
+    }
+}
+
+/// The memtables of a column family, which are swapped together when the main table is flushed.
+#[derive(Debug, Default)]
+struct MemTables {
+    // The main memtable for reading from and writing to.
+    main: SharedMemTable,
+    // A secondary table that is only read from.
+    // It corresponds to the previous main table and is needed to support
+    // reads while the previous main table is still flushed to disk.
+    secondary: SharedMemTable,
+}
+
+/// A column family, shared by all handles of the database.
+///
+/// Reads only lock the memtables briefly to copy the versions they need, scans do so for every
+/// key they reach. Writes and flushes are serialised by the database, so the write-ahead log is
+/// never contended.
+#[derive(Debug)]
+pub(crate) struct ColumnFamily {
+    handle: ColumnFamilyHandle,
+    // Never locked across an await point
+    memtables: RwLock<MemTables>,
+    // The number of entries after which the main table is flushed, no matter its size
+    max_memtable_size: AtomicUsize,
+    // The approximate number of bytes after which the main table is flushed
+    write_buffer_size: usize,
+    file_bundles: FileBundles,
+    // The write-ahead log backing the main table.
+    wal: Mutex<Wal>,
+    // The write-ahead log backing the secondary table, as long as it is not flushed.
+    secondary_wal_path: Mutex<Option<PathBuf>>,
+}
+
+impl ColumnFamily {
+    /// Opens the column family stored in `path`, recovering its SST bundles and write-ahead log.
+    pub(crate) async fn open(
+        handle: ColumnFamilyHandle,
+        path: PathBuf,
+        options: &Options,
+    ) -> Result<Self> {
+        let (wal, main_table) = Wal::open(&path).await?;
+        let file_bundles = FileBundles::recover(path, options.clone()).await?;
+        Ok(Self {
+            handle,
+            memtables: RwLock::new(MemTables {
+                main: SharedMemTable::from(main_table),
+                secondary: Default::default(),
+            }),
+            max_memtable_size: AtomicUsize::new(options.max_memtable_size),
+            write_buffer_size: options.write_buffer_size,
+            file_bundles,
+            wal: Mutex::new(wal),
+            secondary_wal_path: Default::default(),
+        })
+    }
+
+    pub(crate) fn handle(&self) -> &ColumnFamilyHandle {
+        &self.handle
+    }
+
+    pub(crate) fn set_max_memtable_size(
+        &self,
+        max_memtable_size: usize,
+    ) {
+        self.max_memtable_size
+            .store(max_memtable_size, atomic::Ordering::Relaxed);
+    }
+
+    /// Locks the memtables for reading.
+    fn memtables(&self) -> RwLockReadGuard<'_, MemTables> {
+        // Unwrap is OK as the lock is never held while panicking
+        self.memtables.read().unwrap()
+    }
+
+    /// Locks the memtables for writing.
+    fn memtables_mut(&self) -> RwLockWriteGuard<'_, MemTables> {
+        self.memtables.write().unwrap()
+    }
+
+    /// The approximate memory footprint of the memtables in bytes, including the one being flushed.
+    pub(crate) fn memtable_size(&self) -> usize {
+        let memtables = self.memtables();
+        let (main, secondary) = (memtables.main.read(), memtables.secondary.read());
+        main.approximate_size() + secondary.approximate_size()
+    }
+
+    /// The largest sequence number stored in the column family.
+    pub(crate) async fn largest_seq(&self) -> Option<SequenceNumber> {
+        let memtable_seq = self.memtables().main.read().largest_seq();
+        memtable_seq.max(self.file_bundles.inner().read().await.largest_seq())
+    }
+
+    /// Gets the versions of the key that are needed to read it at the snapshot, newest first.
+    /// This is the newest visible version, which may be a tombstone, and if it is a merge operand
+    /// all older versions up to the value the operands are applied to.
+    pub(crate) async fn get_versions_at(
+        &self,
+        key: &[u8],
+        snapshot: SequenceNumber,
+    ) -> Result<Versions> {
+        let mut versions = self.get_from_memtables(key, snapshot)?;
+        if is_complete(&versions) {
+            return Ok(versions);
+        }
+        for bundle in self.file_bundles.inner().read().await.iter() {
+            extend_with_older_versions(
+                &mut versions,
+                get_from_bundle(&bundle, key, snapshot).await?,
+            );
+            if is_complete(&versions) {
+                break;
+            }
+        }
+        Ok(versions)
+    }
+
+    /// Like [`ColumnFamily::get_versions_at`], but for many keys at once.
+    /// Every SST bundle is only consulted once for all keys, reading each of its blocks once.
+    pub(crate) async fn multi_get_versions_at(
+        &self,
+        keys: &[&[u8]],
+        snapshot: SequenceNumber,
+    ) -> Result<Vec<Versions>> {
+        let mut all_versions = keys
+            .iter()
+            .map(|key| self.get_from_memtables(key, snapshot))
+            .collect::<Result<Vec<_>>>()?;
+        // Sorted so that the keys stored in the same block are next to each other
+        let mut pending = (0..keys.len())
+            .filter(|idx| !is_complete(&all_versions[*idx]))
+            .collect::<Vec<_>>();
+        pending.sort_by_key(|idx| keys[*idx]);
+        for bundle in self.file_bundles.inner().read().await.iter() {
+            if pending.is_empty() {
+                break;
+            }
+            for (idx, versions) in multi_get_from_bundle(&bundle, keys, &pending, snapshot).await? {
+                extend_with_older_versions(&mut all_versions[idx], versions);
+            }
+            pending.retain(|idx| !is_complete(&all_versions[*idx]));
+        }
+        Ok(all_versions)
+    }
+
+    /// Gets the versions of the key needed to read it at the snapshot from the main and the
+    /// secondary table.
+    fn get_from_memtables(
+        &self,
+        key: &[u8],
+        snapshot: SequenceNumber,
+    ) -> Result<Versions> {
+        // Both tables are read under the same lock, so that a concurrent flush cannot move the
+        // versions from one to the other in between
+        let memtables = self.memtables();
+        let mut versions = memtables.main.read().get(key, snapshot)?;
+        if !is_complete(&versions) {
+            versions.extend(memtables.secondary.read().get(key, snapshot)?);
+        }
+        Ok(versions)
+    }
+
+    /// Opens cursors over the memtables and SST bundles in the non-empty key range, ordered from
+    /// newest to oldest, and reads the range tombstones overlapping the range.
+    pub(crate) async fn scan(
+        &self,
+        range: &KeyBounds,
+    ) -> Result<(Vec<Box<dyn SortedRun>>, Vec<RangeTombstone>)> {
+        let (mut runs, mut range_tombstones) = {
+            let memtables = self.memtables();
+            let runs: Vec<Box<dyn SortedRun>> = vec![
+                Box::new(MemTableRun::new(memtables.main.clone(), range.clone())),
+                Box::new(MemTableRun::new(memtables.secondary.clone(), range.clone())),
+            ];
+            let range_tombstones = memtables
+                .main
+                .read()
+                .range_tombstones()
+                .iter()
+                .chain(memtables.secondary.read().range_tombstones())
+                .cloned()
+                .collect::<Vec<_>>();
+            (runs, range_tombstones)
+        };
+        // Holding the lock keeps compaction from removing the bundles before they are opened
+        for bundle in self.file_bundles.inner().read().await.iter() {
+            if bundle.key_range.overlaps(range) {
+                runs.push(Box::new(BundleRun::open(&bundle, range).await?));
+                range_tombstones.extend(read_range_tombstones(&bundle).await?);
+            }
+        }
+        range_tombstones.retain(|tombstone| tombstone.overlaps(range));
+        Ok((runs, range_tombstones))
+    }
+
+    /// Logs the writes of a batch and applies them to the main table.
+    /// The database serialises the calls, see [`BaumDb`](crate::BaumDb).
+    pub(crate) async fn write(
+        &self,
+        entries: Vec<WriteEntry>,
+    ) -> Result<()> {
+        self.wal.lock().await.append_batch(&entries).await?;
+        let memtables = self.memtables();
+        let mut main = memtables.main.write();
+        for entry in entries {
+            main.apply(entry);
+        }
+        Ok(())
+    }
+
+    /// Flushes the main table once it reaches the write buffer size or, as a separate upper limit,
+    /// holds the maximum number of entries.
+    pub(crate) async fn maybe_flush_memtable(
+        &self,
+        file_handler: &SstFileHandler,
+        live_snapshots: &BTreeSet<SequenceNumber>,
+        merge_operator: Option<&dyn MergeOperator>,
+    ) -> Result<()> {
+        let max_memtable_size = self.max_memtable_size.load(atomic::Ordering::Relaxed);
+        let is_full = {
+            let memtables = self.memtables();
+            let main = memtables.main.read();
+            main.len() >= max_memtable_size || main.approximate_size() >= self.write_buffer_size
+        };
+        if is_full {
+            self.flush_memtable(file_handler, live_snapshots, merge_operator)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the main table unless it is empty.
+    pub(crate) async fn flush(
+        &self,
+        file_handler: &SstFileHandler,
+        live_snapshots: &BTreeSet<SequenceNumber>,
+        merge_operator: Option<&dyn MergeOperator>,
+    ) -> Result<()> {
+        if !self.memtables().main.read().is_empty() {
+            self.flush_memtable(file_handler, live_snapshots, merge_operator)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the main table to a new SST bundle.
+    /// Like [`ColumnFamily::write`], the calls are serialised by the database, which must not
+    /// flush again after a failed flush until [`ColumnFamily::retry_flush`] succeeded.
+    async fn flush_memtable(
+        &self,
+        file_handler: &SstFileHandler,
+        live_snapshots: &BTreeSet<SequenceNumber>,
+        merge_operator: Option<&dyn MergeOperator>,
+    ) -> Result<()> {
+        let mut secondary_wal_path = self.secondary_wal_path.lock().await;
+        *secondary_wal_path = Some(self.wal.lock().await.rotate().await?);
+        {
+            let mut memtables = self.memtables_mut();
+            // Readers see the previous main table as the secondary table until it is flushed
+            memtables.secondary = mem::take(&mut memtables.main);
+        }
+        self.flush_secondary_table(
+            &mut secondary_wal_path,
+            file_handler,
+            live_snapshots,
+            merge_operator,
+        )
+        .await
+    }
+
+    /// Flushes the secondary table again if its flush failed before.
+    pub(crate) async fn retry_flush(
+        &self,
+        file_handler: &SstFileHandler,
+        live_snapshots: &BTreeSet<SequenceNumber>,
+        merge_operator: Option<&dyn MergeOperator>,
+    ) -> Result<()> {
+        let mut secondary_wal_path = self.secondary_wal_path.lock().await;
+        self.flush_secondary_table(
+            &mut secondary_wal_path,
+            file_handler,
+            live_snapshots,
+            merge_operator,
+        )
+        .await
+    }
+
+    /// Flushes the secondary table to a new SST bundle, unless it was flushed already.
+    /// The table is kept until the flush succeeds, so that it can be retried.
+    async fn flush_secondary_table(
+        &self,
+        secondary_wal_path: &mut Option<PathBuf>,
+        file_handler: &SstFileHandler,
+        live_snapshots: &BTreeSet<SequenceNumber>,
+        merge_operator: Option<&dyn MergeOperator>,
+    ) -> Result<()> {
+        if secondary_wal_path.is_none() {
+            return Ok(());
+        }
+        // Iterators may still read the table, so it is only changed as a copy
+        let mut previous_memtable = self.memtables().secondary.read().clone();
+        if let Some(merge_operator) = merge_operator {
+            // The SST bundles may hold older versions the remaining operands are applied to
+            previous_memtable.resolve_merge_operands(merge_operator, |_| false);
+        }
+        previous_memtable.retain_visible_versions(live_snapshots);
+        file_handler
+            .flush(&self.file_bundles, previous_memtable)
+            .await?;
+        // The flushed bundle holds the same versions now, reading them twice would apply merge
+        // operands twice
+        self.memtables_mut().secondary = Default::default();
+        // The previous memtable is committed to disk now, so its log is no longer needed. Should we
+        // crash before it is removed, its versions are replayed into the memtable again, where
+        // reads and compactions ignore them next to the flushed ones with the same sequence number
+        if let Some(previous_wal_path) = secondary_wal_path.take() {
+            remove_file(previous_wal_path).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Looks up the key in the SST bundle.
+/// Returns the versions stored for the key that are needed to read it at the snapshot, see
+/// [`versions_to_read`].
+async fn get_from_bundle(
+    bundle: &SstFileBundle<'_>,
+    key: &[u8],
+    snapshot: SequenceNumber,
+) -> Result<Versions> {
+    if !bundle.key_range.contains(key) {
+        return Ok(vec![]);
+    }
+    let range_tombstones = read_range_tombstones(bundle).await?;
+    let versions = get_versions_from_bundle(bundle, key, snapshot).await?;
+    Ok(apply_range_tombstones(
+        versions,
+        &range_tombstones,
+        key,
+        snapshot,
+    ))
+}
+
+/// Like [`get_from_bundle`], but ignoring the range tombstones of the bundle.
+async fn get_versions_from_bundle(
+    bundle: &SstFileBundle<'_>,
+    key: &[u8],
+    snapshot: SequenceNumber,
+) -> Result<Versions> {
+    let bloom_filter = DefaultBloomFilter::try_from_file(bundle.bloom_filter_file_path).await?;
+    if !bloom_filter.may_contain_key(key) {
+        return Ok(vec![]);
+    }
+    let index = read_index(bundle.index_file_path).await?;
+    let Some(offset) = find_block_offset(&index, key) else {
+        return Ok(vec![]);
+    };
+    let block = read_block(bundle.main_data_file_path, offset).await?;
+    Ok(versions_to_read(&versions_in_block(&block, key), snapshot))
+}
+
+/// Looks up the keys at the `candidates` indices, which are sorted by key, in the SST bundle.
+/// Returns the versions needed to read them at the snapshot for the keys the bundle contains.
+/// The blocks holding the keys are read concurrently.
+async fn multi_get_from_bundle(
+    bundle: &SstFileBundle<'_>,
+    keys: &[&[u8]],
+    candidates: &[usize],
+    snapshot: SequenceNumber,
+) -> Result<Vec<(usize, Versions)>> {
+    let in_key_range = candidates
+        .iter()
+        .copied()
+        .filter(|idx| bundle.key_range.contains(keys[*idx]))
+        .collect::<Vec<_>>();
+    if in_key_range.is_empty() {
+        return Ok(vec![]);
+    }
+    let range_tombstones = read_range_tombstones(bundle).await?;
+    let found = multi_get_versions_from_bundle(bundle, keys, &in_key_range, snapshot).await?;
+    if range_tombstones.is_empty() {
+        return Ok(found);
+    }
+    // Keys the bundle does not store may still be deleted by its range tombstones
+    let mut found = found.into_iter().collect::<BTreeMap<_, _>>();
+    Ok(in_key_range
+        .into_iter()
+        .map(|idx| {
+            let versions = found.remove(&idx).unwrap_or_default();
+            let versions = apply_range_tombstones(versions, &range_tombstones, keys[idx], snapshot);
+            (idx, versions)
+        })
+        .filter(|(_, versions)| !versions.is_empty())
+        .collect())
+}
+
+/// Like [`multi_get_from_bundle`], but ignoring the range tombstones of the bundle.
+async fn multi_get_versions_from_bundle(
+    bundle: &SstFileBundle<'_>,
+    keys: &[&[u8]],
+    candidates: &[usize],
+    snapshot: SequenceNumber,
+) -> Result<Vec<(usize, Versions)>> {
+    let bloom_filter = DefaultBloomFilter::try_from_file(bundle.bloom_filter_file_path).await?;
+    let candidates = candidates
+        .iter()
+        .copied()
+        .filter(|idx| bloom_filter.may_contain_key(keys[*idx]))
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        return Ok(vec![]);
+    }
+    let index = read_index(bundle.index_file_path).await?;
+    let mut candidates_by_block: BTreeMap<u64, Vec<usize>> = BTreeMap::new();
+    for idx in candidates {
+        if let Some(offset) = find_block_offset(&index, keys[idx]) {
+            candidates_by_block.entry(offset).or_default().push(idx);
+        }
+    }
+
+    let mut block_reads = JoinSet::new();
+    for offset in candidates_by_block.keys().copied() {
+        let main_data_file_path = bundle.main_data_file_path.to_path_buf();
+        block_reads.spawn(async move {
+            let block = read_block(&main_data_file_path, offset).await;
+            (offset, block)
+        });
+    }
+    let mut found = Vec::new();
+    while let Some(block_read) = block_reads.join_next().await {
+        let (offset, block) = match block_read {
+            Ok(block_read) => block_read,
+            Err(error) if error.is_panic() => panic::resume_unwind(error.into_panic()),
+            // The reads are only cancelled when the runtime shuts down
+            Err(_) => return Err(Error::Closed),
+        };
+        let block = block?;
+        for idx in candidates_by_block[&offset].iter().copied() {
+            let versions = versions_to_read(&versions_in_block(&block, keys[idx]), snapshot);
+            if !versions.is_empty() {
+                found.push((idx, versions));
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Reads all versions of the key stored in the decompressed data block, newest first.
+fn versions_in_block(
+    block: &[u8],
+    key: &[u8],
+) -> Versions {
+    let mut decompressed_cursor = Cursor::new(block);
+    // All versions of a key are stored in the same block, newest first
+    let mut versions = Vec::new();
+    while let Ok(KeyValue {
+        key: existing_key,
+        value: existing_value,
+    }) = read_key_value(&mut decompressed_cursor)
+    {
+        match existing_key.as_ref().cmp(key) {
+            Ordering::Less => {}
+            Ordering::Equal => versions.push(existing_value),
+            Ordering::Greater => break,
+        }
+    }
+    versions
+}
+
+/// A cursor over the entries of an SST bundle in a key range, see [`SortedRun`].
+/// It reads the data blocks as it reaches them, keeping only the last one in memory.
+#[derive(Debug)]
+struct BundleRun {
+    // Kept open, so that the bundle can still be read once compaction removed it
+    main_data_file: File,
+    main_data_file_path: PathBuf,
+    index: Vec<KeyOffset>,
+    range: KeyBounds,
+    gap: Gap,
+    // The position of the last block read in the index and its entries
+    block: Option<(usize, Vec<(Bytes, Versions)>)>,
+}
+
+impl BundleRun {
+    /// Opens a cursor over the non-empty key range of the SST bundle, reading its sparse index.
+    async fn open(
+        bundle: &SstFileBundle<'_>,
+        range: &KeyBounds,
+    ) -> Result<Self> {
+        let index = read_index(bundle.index_file_path).await?;
+        let main_data_file = File::open(bundle.main_data_file_path).await?;
+        Ok(Self {
+            main_data_file,
+            main_data_file_path: bundle.main_data_file_path.to_path_buf(),
+            index,
+            range: range.clone(),
+            gap: Gap::Start,
+            block: None,
+        })
+    }
+
+    /// The entries of the block at the position in the index, which is only read if it is not
+    /// the last block read.
+    async fn block_entries(
+        &mut self,
+        block_idx: usize,
+    ) -> Result<&[(Bytes, Versions)]> {
+        if self.block.as_ref().is_none_or(|(idx, _)| *idx != block_idx) {
+            let block = read_block_from(
+                &mut self.main_data_file,
+                &self.main_data_file_path,
+                self.index[block_idx].offset,
+            )
+            .await?;
+            self.block = Some((block_idx, entries_in_block(block)));
+        }
+        // Unwrap is OK as the block was set above
+        Ok(&self.block.as_ref().unwrap().1)
+    }
+}
+
+#[async_trait]
+impl SortedRun for BundleRun {
+    async fn read_next(&mut self) -> Result<Option<(Bytes, Versions)>> {
+        let Some(bounds) = self.gap.bounds_after(&self.range) else {
+            return Ok(None);
+        };
+        let mut block_idx = match bounds.start_bound() {
+            Bound::Included(start) | Bound::Excluded(start) => self
+                .index
+                .partition_point(|entry| entry.key <= start)
+                .saturating_sub(1),
+            Bound::Unbounded => 0,
+        };
+        while block_idx < self.index.len() && !is_past_end(&self.index[block_idx].key, &bounds) {
+            let entries = self.block_entries(block_idx).await?;
+            let n_skipped = entries.partition_point(|(key, _)| is_before_start(key, &bounds));
+            if let Some((key, versions)) = entries.get(n_skipped) {
+                return Ok((!is_past_end(key, &bounds)).then(|| (key.clone(), versions.clone())));
+            }
+            block_idx += 1;
+        }
+        Ok(None)
+    }
+
+    async fn read_prev(&mut self) -> Result<Option<(Bytes, Versions)>> {
+        let Some(bounds) = self.gap.bounds_before(&self.range) else {
+            return Ok(None);
+        };
+        // The blocks starting with a key in front of the end of the bounds
+        let mut n_blocks = self
+            .index
+            .partition_point(|entry| !is_past_end(&entry.key, &bounds));
+        while let Some(block_idx) = n_blocks.checked_sub(1) {
+            let entries = self.block_entries(block_idx).await?;
+            let n_kept = entries.partition_point(|(key, _)| !is_past_end(key, &bounds));
+            if let Some((key, versions)) = entries[..n_kept].last() {
+                return Ok(
+                    (!is_before_start(key, &bounds)).then(|| (key.clone(), versions.clone()))
+                );
+            }
+            n_blocks = block_idx;
+        }
+        Ok(None)
+    }
+
+    fn seek(
+        &mut self,
+        gap: Gap,
+    ) {
+        self.gap = gap;
+    }
+}
+
+/// Reads the entries of the decompressed data block in order, each with all versions of its key.
+fn entries_in_block(block: Vec<u8>) -> Vec<(Bytes, Versions)> {
+    let mut decompressed_cursor = Cursor::new(block);
+    let mut entries: Vec<(Bytes, Versions)> = Vec::new();
+    while let Ok(KeyValue { key, value }) = read_key_value(&mut decompressed_cursor) {
+        match entries.last_mut() {
+            Some((last_key, versions)) if *last_key == key => versions.push(value),
+            _ => entries.push((key, vec![value])),
+        }
+    }
+    entries
+}
+
+/// Reads the sparse index holding the first key of every data block.
+/// Invariant here is that the index is already sorted.
+async fn read_index(index_file_path: &Path) -> Result<Vec<KeyOffset>> {
+    let mut index_file = File::open(index_file_path).await?;
+    let mut index_as_bytes = Vec::<u8>::new();
+    index_file.read_to_end(&mut index_as_bytes).await?;
+    strip_checksum(&mut index_as_bytes, index_file_path)?;
+
+    let mut index = Vec::new();
+    let mut cursor = Cursor::new(index_as_bytes);
+    while let Ok(key_offset) = read_key_offset(&mut cursor) {
+        index.push(key_offset);
+    }
+    Ok(index)
+}
+
+/// Finds the offset of the only data block that may contain the key, i.e. the last block whose
+/// first key is not greater than the key.
+fn find_block_offset(
+    index: &[KeyOffset],
+    key: &[u8],
+) -> Option<u64> {
+    let n_candidate_blocks = index.partition_point(|entry| entry.key.as_ref() <= key);
+    n_candidate_blocks
+        .checked_sub(1)
+        .map(|block_idx| index[block_idx].offset)
+}
+
+/// Reads and decompresses the data block at `offset`.
+async fn read_block(
+    main_data_file_path: &Path,
+    offset: u64,
+) -> Result<Vec<u8>> {
+    let mut main_data_file = File::open(main_data_file_path).await?;
+    read_block_from(&mut main_data_file, main_data_file_path, offset).await
+}
+
+/// Like [`read_block`], but reading from the open main data file.
+async fn read_block_from(
+    main_data_file: &mut File,
+    main_data_file_path: &Path,
+    offset: u64,
+) -> Result<Vec<u8>> {
+    main_data_file.seek(SeekFrom::Start(offset)).await?;
+    let encoded_block_length = main_data_file.read_u64().await? as usize;
+    let block_checksum = main_data_file.read_u32().await?;
+    let mut raw_block = vec![0; encoded_block_length];
+    main_data_file.read_exact(&mut raw_block).await?;
+    decode_block(&raw_block, block_checksum, main_data_file_path, offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[test]
+    fn find_block_offset_picks_last_block_starting_before_key() {
+        let index: Vec<KeyOffset> = [("b", 0), ("d", 100), ("f", 200)]
+            .into_iter()
+            .map(|(key, offset)| KeyOffset {
+                key: Bytes::from(key),
+                offset,
+            })
+            .collect();
+
+        assert_eq!(find_block_offset(&index, b"a"), None);
+        assert_eq!(find_block_offset(&index, b"b"), Some(0));
+        assert_eq!(find_block_offset(&index, b"c"), Some(0));
+        assert_eq!(find_block_offset(&index, b"d"), Some(100));
+        assert_eq!(find_block_offset(&index, b"e"), Some(100));
+        assert_eq!(find_block_offset(&index, b"f"), Some(200));
+        assert_eq!(find_block_offset(&index, b"z"), Some(200));
+        assert_eq!(find_block_offset(&[], b"a"), None);
+    }
+
+    #[test]
+    fn column_family_names_must_be_usable_as_directory_names() {
+        assert!(validate_column_family_name("users").is_ok());
+        assert!(validate_column_family_name("event_log-2").is_ok());
+        assert!(validate_column_family_name("").is_err());
+        assert!(validate_column_family_name("../users").is_err());
+        assert!(validate_column_family_name("a/b").is_err());
+    }
+}
@@ -1,197 +1,874 @@
-use std::io::Cursor;
-use std::io::Read;
-use std::io::SeekFrom;
-use std::mem;
+use std::ops::RangeBounds;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::sync::RwLockReadGuard;
+use std::time::Duration;
 
-use anyhow::Result;
 use async_trait::async_trait;
-use flate2::read::GzDecoder;
+use bytes::Bytes;
 use tokio::fs::create_dir;
-use tokio::fs::File;
-use tokio::io::AsyncReadExt;
-use tokio::io::AsyncSeekExt;
-
-use crate::bloom_filter::BloomFilter;
-use crate::bloom_filter::DefaultBloomFilter;
-use crate::deserialization::read_key_offset;
-use crate::deserialization::read_key_value;
-use crate::deserialization::KeyOffset;
-use crate::deserialization::KeyValue;
-use crate::file_handling::DataHandling;
-use crate::file_handling::FileHandling;
-use crate::file_handling::SstFileBundle;
+use tokio::fs::create_dir_all;
+use tokio::fs::read_dir;
+use tokio::sync::Mutex;
+use tokio::sync::MutexGuard;
+
+use crate::column_family::validate_column_family_name;
+use crate::column_family::ColumnFamily;
+use crate::column_family::ColumnFamilyHandle;
+use crate::column_family::DEFAULT_COLUMN_FAMILY_NAME;
+use crate::error::BackgroundErrorState;
+use crate::error::Error;
+use crate::error::Result;
 use crate::file_handling::SstFileHandler;
-use crate::memtable::MemTable;
-use crate::memtable::MemTableGet;
-use crate::memtable::MemTableReadOnly;
-use crate::memtable::MemTableWrite;
+use crate::iterator::is_empty_range;
+use crate::iterator::to_key_bounds;
+use crate::iterator::DbIterator;
+use crate::iterator::KeyBounds;
+use crate::memtable::current_timestamp;
 use crate::memtable::MemValue;
+use crate::memtable::SequenceNumber;
+use crate::merge_operator::resolve_versions;
+use crate::merge_operator::MergeOperator;
+use crate::options::Options;
+use crate::snapshot::Snapshot;
+use crate::snapshot::SnapshotList;
+use crate::transaction::Transaction;
+use crate::write_batch::WriteBatch;
+
+/// The directory within the database directory holding the column families other than the
+/// default one, which is stored in the database directory itself.
+const COLUMN_FAMILIES_DIR: &str = "column_families";
 
+/// The core key-value API. Keys and values are arbitrary bytes, keys are ordered lexicographically
+/// by their raw bytes.
+///
+/// A write returns once it is synced to the write-ahead log on disk, so it is recovered even if
+/// the process or the OS crashes right after.
 #[async_trait]
 pub trait DB {
-    async fn get(
+    async fn get<K>(
         &self,
-        key: &str,
-    ) -> Result<Option<String>>;
+        key: K,
+    ) -> Result<Option<Bytes>>
+    where
+        K: AsRef<[u8]> + Send + Sync;
 
-    async fn put(
-        &mut self,
-        key: String,
-        value: String,
-    ) -> Result<()>;
+    async fn put<K, V>(
+        &self,
+        key: K,
+        value: V,
+    ) -> Result<()>
+    where
+        K: Into<Bytes> + Send,
+        V: Into<Bytes> + Send;
+
+    async fn delete<K>(
+        &self,
+        key: K,
+    ) -> Result<()>
+    where
+        K: AsRef<[u8]> + Send + Sync;
 
-    async fn delete(
-        &mut self,
-        key: &str,
+    /// Deletes all keys from `start` (inclusive) to `end` (exclusive).
+    /// Only a single range tombstone is written, no matter how many keys are deleted.
+    /// Fails with [`Error::InvalidArgument`] if `start` is not smaller than `end`.
+    async fn delete_range<K>(
+        &self,
+        start: K,
+        end: K,
+    ) -> Result<()>
+    where
+        K: AsRef<[u8]> + Send + Sync;
+
+    /// Gets the values of the keys, in the same order as the keys.
+    /// Cheaper than calling [`DB::get`] for every key, as every SST bundle is consulted only once
+    /// and its blocks are read concurrently.
+    async fn multi_get<K>(
+        &self,
+        keys: &[K],
+    ) -> Result<Vec<Option<Bytes>>>
+    where
+        K: AsRef<[u8]> + Sync;
+
+    /// Puts the value for the key, which expires `ttl` after it was written.
+    /// An expired key reads as absent and is eventually dropped by compaction.
+    async fn put_with_ttl<K, V>(
+        &self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> Result<()>
+    where
+        K: Into<Bytes> + Send,
+        V: Into<Bytes> + Send;
+
+    /// Merges the operand into the value of the key using the database's [`MergeOperator`].
+    /// Fails if the database was opened without a merge operator.
+    async fn merge<K, V>(
+        &self,
+        key: K,
+        operand: V,
+    ) -> Result<()>
+    where
+        K: Into<Bytes> + Send,
+        V: Into<Bytes> + Send;
+
+    /// Applies all writes of the batch atomically.
+    async fn write(
+        &self,
+        batch: WriteBatch,
     ) -> Result<()>;
+
+    /// Returns an ordered iterator over all key-value pairs whose keys fall into the range.
+    /// The data blocks are only read while iterating, see [`DbIterator`].
+    async fn scan<K, R>(
+        &self,
+        range: R,
+    ) -> Result<DbIterator>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K> + Send;
+
+    /// Takes a snapshot of the current state of the database.
+    fn snapshot(&self) -> Snapshot;
+
+    /// Gets the value of the key as of the snapshot.
+    async fn get_with_snapshot<K>(
+        &self,
+        key: K,
+        snapshot: &Snapshot,
+    ) -> Result<Option<Bytes>>
+    where
+        K: AsRef<[u8]> + Send + Sync;
+
+    /// Like [`DB::scan`], but only iterates over the state of the database as of the snapshot.
+    async fn scan_with_snapshot<K, R>(
+        &self,
+        range: R,
+        snapshot: &Snapshot,
+    ) -> Result<DbIterator>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K> + Send;
+
+    /// Like [`DB::get`], but reads from the column family instead of the default one.
+    async fn get_cf<K>(
+        &self,
+        column_family: &ColumnFamilyHandle,
+        key: K,
+    ) -> Result<Option<Bytes>>
+    where
+        K: AsRef<[u8]> + Send + Sync;
+
+    /// Like [`DB::put`], but writes to the column family instead of the default one.
+    async fn put_cf<K, V>(
+        &self,
+        column_family: &ColumnFamilyHandle,
+        key: K,
+        value: V,
+    ) -> Result<()>
+    where
+        K: Into<Bytes> + Send,
+        V: Into<Bytes> + Send;
+
+    /// Like [`DB::delete`], but deletes from the column family instead of the default one.
+    async fn delete_cf<K>(
+        &self,
+        column_family: &ColumnFamilyHandle,
+        key: K,
+    ) -> Result<()>
+    where
+        K: AsRef<[u8]> + Send + Sync;
+
+    /// Like [`DB::scan`], but iterates over the column family instead of the default one.
+    async fn scan_cf<K, R>(
+        &self,
+        column_family: &ColumnFamilyHandle,
+        range: R,
+    ) -> Result<DbIterator>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K> + Send;
+
+    /// Convenience wrapper around [`DB::get`] for values that are UTF-8 strings.
+    /// Fails if the stored value is not valid UTF-8.
+    async fn get_string<K>(
+        &self,
+        key: K,
+    ) -> Result<Option<String>>
+    where
+        K: AsRef<[u8]> + Send + Sync,
+    {
+        self.get(key)
+            .await?
+            .map(|value| {
+                String::from_utf8(value.to_vec())
+                    .map_err(|error| Error::InvalidData(error.to_string()))
+            })
+            .transpose()
+    }
 }
 
-#[derive(Debug)]
+/// A handle to an open database.
+///
+/// Handles are cheap to clone and can be shared across tasks and threads, all clones accessing the
+/// same database. Reads run concurrently, while writes are applied one batch at a time.
+#[derive(Debug, Clone)]
 pub struct BaumDb {
-    // The main memtable for reading from and writing to.
-    main_table: MemTable,
-    // A secondary table that can only be read from.
-    // It corresponds to the previous main table and is needed to support
-    // reads while the previous main table is still flushed to disk.
-    secondary_table: MemTableReadOnly,
-    max_memtable_size: usize,
+    path: PathBuf,
+    // Indexed by the id of their handle, the default column family comes first.
+    // Never locked across an await point.
+    column_families: Arc<RwLock<Vec<Arc<ColumnFamily>>>>,
     file_handler: SstFileHandler,
+    // Serialises the writes, including the memtable flushes they trigger.
+    write_lock: Arc<Mutex<()>>,
+    // The sequence number of the last write applied to any of the memtables.
+    // Only advanced once all writes of a batch are applied, so readers see all of them or none.
+    last_seq: Arc<AtomicU64>,
+    snapshots: SnapshotList,
+    options: Options,
+    background_error: BackgroundErrorState,
+    closed: Arc<AtomicBool>,
 }
 
 #[async_trait]
 impl DB for BaumDb {
-    async fn get(
+    async fn get<K>(
         &self,
-        key: &str,
-    ) -> Result<Option<String>> {
-        if let Some(value) = self.main_table.get(key)? {
-            return Ok(Some(value));
-        }
-        // Check the secondary table (representing the previous memtable)
-        match self.secondary_table.get(key)? {
-            Some(value) => Ok(Some(value)),
-            None => {
-                let file_path_bundles = self.file_handler.file_bundles();
-                for SstFileBundle {
-                    main_data_file_path,
-                    index_file_path,
-                    bloom_filter_file_path,
-                } in file_path_bundles.inner().read().await.iter()
-                {
-                    let bloom_filter =
-                        DefaultBloomFilter::try_from_file(bloom_filter_file_path).await?;
-                    if bloom_filter.may_contain_key(key) {
-                        let mut index_file = File::open(index_file_path).await?;
-                        let mut index_as_bytes = Vec::<u8>::new();
-                        index_file.read_to_end(&mut index_as_bytes).await?;
-
-                        // Invariant here is that the index is already sorted
-                        // TODO: we're over-allocating here - is there a better way?
-                        let mut index_vec = Vec::with_capacity(index_as_bytes.len());
-                        let mut cursor = Cursor::new(index_as_bytes);
-                        while let Ok(KeyOffset {
-                            key: indexed_key,
-                            offset,
-                        }) = read_key_offset(&mut cursor)
-                        {
-                            index_vec.push((indexed_key, offset));
-                        }
-                        let mut index_iter = index_vec.iter().peekable();
-                        if let Some((_idx, offset)) = index_iter
-                            .next_if(|(idx, _)| idx.as_str() < key)
-                            .or_else(|| index_iter.next())
-                        {
-                            let mut main_data_file = File::open(&main_data_file_path).await?;
-                            main_data_file.seek(SeekFrom::Start(*offset)).await?;
-                            let encoded_block_length = main_data_file.read_u64().await? as usize;
-                            let mut raw_block = vec![0; encoded_block_length];
-                            main_data_file.read_exact(&mut raw_block).await?;
-
-                            let mut decoder = GzDecoder::new(raw_block.as_slice());
-                            // The vec will very likely end up larger than the `n_bytes_to_read`,
-                            // but that's the best we know at this point and it'll save some reallocations.
-                            let mut decompressed_block = Vec::with_capacity(raw_block.len());
-                            decoder.read_to_end(&mut decompressed_block)?;
-                            let mut decompressed_cursor = Cursor::new(decompressed_block);
-                            while let Ok(KeyValue {
-                                key: existing_key,
-                                value: existing_value,
-                            }) = read_key_value(&mut decompressed_cursor)
-                            {
-                                if existing_key == key {
-                                    return match existing_value {
-                                        MemValue::Put(existing_value_str) => {
-                                            Ok(Some(existing_value_str))
-                                        }
-                                        MemValue::Delete => Ok(None),
-                                    };
-                                }
-                            }
-                        }
-                    }
-                }
-                Ok(None)
-            }
-        }
+        key: K,
+    ) -> Result<Option<Bytes>>
+    where
+        K: AsRef<[u8]> + Send + Sync,
+    {
+        let column_family = self.default_cf()?;
+        // Held until the read is done, so that compactions keep the versions visible to it
+        let snapshot = self.snapshot();
+        self.get_at(&column_family, key.as_ref(), snapshot.sequence_number())
+            .await
     }
 
-    async fn put(
-        &mut self,
-        key: String,
-        value: String,
-    ) -> Result<()> {
-        self.main_table.put(key, value)?;
-        self.maybe_flush_memtable().await
+    async fn put<K, V>(
+        &self,
+        key: K,
+        value: V,
+    ) -> Result<()>
+    where
+        K: Into<Bytes> + Send,
+        V: Into<Bytes> + Send,
+    {
+        let mut batch = WriteBatch::new();
+        batch.put(key, value);
+        self.write(batch).await
+    }
+
+    async fn delete<K>(
+        &self,
+        key: K,
+    ) -> Result<()>
+    where
+        K: AsRef<[u8]> + Send + Sync,
+    {
+        let mut batch = WriteBatch::new();
+        batch.delete(key);
+        self.write(batch).await
     }
 
-    async fn delete(
-        &mut self,
-        key: &str,
+    async fn delete_range<K>(
+        &self,
+        start: K,
+        end: K,
+    ) -> Result<()>
+    where
+        K: AsRef<[u8]> + Send + Sync,
+    {
+        let mut batch = WriteBatch::new();
+        batch.delete_range(start, end);
+        self.write(batch).await
+    }
+
+    async fn multi_get<K>(
+        &self,
+        keys: &[K],
+    ) -> Result<Vec<Option<Bytes>>>
+    where
+        K: AsRef<[u8]> + Sync,
+    {
+        let keys = keys.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+        let snapshot = self.snapshot();
+        let all_versions = self
+            .default_cf()?
+            .multi_get_versions_at(&keys, snapshot.sequence_number())
+            .await?;
+        let now = current_timestamp();
+        keys.into_iter()
+            .zip(all_versions)
+            .map(|(key, versions)| {
+                resolve_versions(key, versions, self.options.merge_operator.as_deref(), now)
+            })
+            .collect()
+    }
+
+    async fn put_with_ttl<K, V>(
+        &self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> Result<()>
+    where
+        K: Into<Bytes> + Send,
+        V: Into<Bytes> + Send,
+    {
+        let mut batch = WriteBatch::new();
+        batch.put_with_ttl(key, value, ttl);
+        self.write(batch).await
+    }
+
+    async fn merge<K, V>(
+        &self,
+        key: K,
+        operand: V,
+    ) -> Result<()>
+    where
+        K: Into<Bytes> + Send,
+        V: Into<Bytes> + Send,
+    {
+        let mut batch = WriteBatch::new();
+        batch.merge(key, operand);
+        self.write(batch).await
+    }
+
+    async fn write(
+        &self,
+        batch: WriteBatch,
     ) -> Result<()> {
-        self.main_table.delete(key)?;
-        self.maybe_flush_memtable().await
+        let column_family = self.default_cf()?;
+        self.write_to(&column_family, batch).await
+    }
+
+    async fn scan<K, R>(
+        &self,
+        range: R,
+    ) -> Result<DbIterator>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K> + Send,
+    {
+        let column_family = self.default_cf()?;
+        let snapshot = self.snapshot();
+        self.scan_at(
+            &column_family,
+            to_key_bounds(range),
+            snapshot.sequence_number(),
+        )
+        .await
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        self.snapshots.acquire(self.last_seq())
+    }
+
+    async fn get_with_snapshot<K>(
+        &self,
+        key: K,
+        snapshot: &Snapshot,
+    ) -> Result<Option<Bytes>>
+    where
+        K: AsRef<[u8]> + Send + Sync,
+    {
+        let column_family = self.default_cf()?;
+        self.get_at(&column_family, key.as_ref(), snapshot.sequence_number())
+            .await
+    }
+
+    async fn scan_with_snapshot<K, R>(
+        &self,
+        range: R,
+        snapshot: &Snapshot,
+    ) -> Result<DbIterator>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K> + Send,
+    {
+        let column_family = self.default_cf()?;
+        self.scan_at(
+            &column_family,
+            to_key_bounds(range),
+            snapshot.sequence_number(),
+        )
+        .await
+    }
+
+    async fn get_cf<K>(
+        &self,
+        column_family: &ColumnFamilyHandle,
+        key: K,
+    ) -> Result<Option<Bytes>>
+    where
+        K: AsRef<[u8]> + Send + Sync,
+    {
+        let column_family = self.column_family(column_family)?;
+        let snapshot = self.snapshot();
+        self.get_at(&column_family, key.as_ref(), snapshot.sequence_number())
+            .await
+    }
+
+    async fn put_cf<K, V>(
+        &self,
+        column_family: &ColumnFamilyHandle,
+        key: K,
+        value: V,
+    ) -> Result<()>
+    where
+        K: Into<Bytes> + Send,
+        V: Into<Bytes> + Send,
+    {
+        let mut batch = WriteBatch::new();
+        batch.put(key, value);
+        let column_family = self.column_family(column_family)?;
+        self.write_to(&column_family, batch).await
+    }
+
+    async fn delete_cf<K>(
+        &self,
+        column_family: &ColumnFamilyHandle,
+        key: K,
+    ) -> Result<()>
+    where
+        K: AsRef<[u8]> + Send + Sync,
+    {
+        let mut batch = WriteBatch::new();
+        batch.delete(key);
+        let column_family = self.column_family(column_family)?;
+        self.write_to(&column_family, batch).await
+    }
+
+    async fn scan_cf<K, R>(
+        &self,
+        column_family: &ColumnFamilyHandle,
+        range: R,
+    ) -> Result<DbIterator>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K> + Send,
+    {
+        let column_family = self.column_family(column_family)?;
+        let snapshot = self.snapshot();
+        self.scan_at(
+            &column_family,
+            to_key_bounds(range),
+            snapshot.sequence_number(),
+        )
+        .await
     }
 }
 
 impl BaumDb {
+    /// Creates a new database in `sst_dir_path` or reopens the one already stored there, see
+    /// [`BaumDb::open`].
     pub async fn new<P>(
         sst_dir_path: P,
         max_memtable_size: usize,
-    ) -> Self
+    ) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        P: Into<PathBuf>,
+    {
+        Self::open(sst_dir_path, max_memtable_size).await
+    }
+
+    /// Opens the database in `sst_dir_path`, creating the directory if it does not exist yet.
+    ///
+    /// SST bundles and write-ahead logs left in the directory by a previous run are recovered,
+    /// so all data written before is readable again. This includes all column families, which
+    /// use `max_memtable_size` until they are opened with their own limit.
+    /// The other settings are the ones the database was opened with before, see [`Options`].
+    pub async fn open<P>(
+        sst_dir_path: P,
+        max_memtable_size: usize,
+    ) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        P: Into<PathBuf>,
+    {
+        let options = Options::default().max_memtable_size(max_memtable_size);
+        Self::open_with_options(sst_dir_path, options).await
+    }
+
+    /// Like [`BaumDb::open`], but combines the operands written with [`DB::merge`] using the
+    /// `merge_operator`.
+    /// A database that contains merge operands must always be opened with the same operator.
+    pub async fn open_with_merge_operator<P>(
+        sst_dir_path: P,
+        max_memtable_size: usize,
+        merge_operator: Arc<dyn MergeOperator>,
+    ) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        P: Into<PathBuf>,
+    {
+        let options = Options::default()
+            .max_memtable_size(max_memtable_size)
+            .merge_operator(merge_operator);
+        Self::open_with_options(sst_dir_path, options).await
+    }
+
+    /// Like [`BaumDb::open`], but with all settings taken from the options.
+    /// Fails with [`Error::InvalidArgument`] if they are invalid or incompatible with the ones the
+    /// database was opened with before, see [`Options`].
+    pub async fn open_with_options<P>(
+        sst_dir_path: P,
+        options: Options,
+    ) -> Result<Self>
     where
         P: AsRef<Path>,
         P: Into<PathBuf>,
     {
+        options.validate()?;
         let path: PathBuf = sst_dir_path.into();
         if !path.exists() {
-            create_dir(&path)
-                .await
-                .expect("be able to create directory");
+            create_dir(&path).await?;
         }
+        let options = options.persist(&path).await?;
+        let default_handle = ColumnFamilyHandle::new(0, DEFAULT_COLUMN_FAMILY_NAME);
+        let mut column_families = vec![Arc::new(
+            ColumnFamily::open(default_handle, path.clone(), &options).await?,
+        )];
+        for name in existing_column_family_names(&path).await? {
+            let handle = ColumnFamilyHandle::new(column_families.len(), &name);
+            let column_family_path = column_family_path(&path, &name);
+            column_families.push(Arc::new(
+                ColumnFamily::open(handle, column_family_path, &options).await?,
+            ));
+        }
+        // Continue after the newest write that made it to disk
+        let mut last_seq = 0;
+        for column_family in column_families.iter() {
+            last_seq = last_seq.max(column_family.largest_seq().await.unwrap_or_default());
+        }
+        let snapshots = SnapshotList::default();
+        let background_error = BackgroundErrorState::default();
+        let file_handler =
+            SstFileHandler::new(snapshots.clone(), &options, background_error.clone());
 
-        Self {
-            main_table: Default::default(),
-            secondary_table: Default::default(),
-            max_memtable_size,
-            file_handler: SstFileHandler::new(path),
+        Ok(Self {
+            path,
+            column_families: Arc::new(RwLock::new(column_families)),
+            file_handler,
+            write_lock: Default::default(),
+            last_seq: Arc::new(AtomicU64::new(last_seq)),
+            snapshots,
+            options,
+            background_error,
+            closed: Default::default(),
+        })
+    }
+
+    /// The handle of the default column family, which is read and written by the methods that
+    /// do not take a [`ColumnFamilyHandle`].
+    pub fn default_column_family(&self) -> ColumnFamilyHandle {
+        self.column_families()[0].handle().clone()
+    }
+
+    /// Opens the column family with the given name, creating it if it does not exist yet.
+    ///
+    /// Its memtable is flushed once it holds `max_memtable_size` entries or reaches
+    /// [`Options::write_buffer_size`]. Names may only consist of ASCII letters, digits, `-` and `_`.
+    pub async fn open_column_family(
+        &self,
+        name: &str,
+        max_memtable_size: usize,
+    ) -> Result<ColumnFamilyHandle> {
+        // Keeps concurrent calls from creating the same column family twice
+        let _writer = self.write_lock.lock().await;
+        self.check_open()?;
+        if let Some(column_family) = self
+            .column_families()
+            .iter()
+            .find(|column_family| column_family.handle().name() == name)
+        {
+            column_family.set_max_memtable_size(max_memtable_size);
+            return Ok(column_family.handle().clone());
         }
+        validate_column_family_name(name)?;
+        let column_family_path = column_family_path(&self.path, name);
+        create_dir_all(&column_family_path).await?;
+        let handle = ColumnFamilyHandle::new(self.column_families().len(), name);
+        let options = self.options.clone().max_memtable_size(max_memtable_size);
+        let column_family =
+            ColumnFamily::open(handle.clone(), column_family_path, &options).await?;
+        self.column_families
+            .write()
+            .unwrap()
+            .push(Arc::new(column_family));
+        Ok(handle)
+    }
+
+    fn column_families(&self) -> RwLockReadGuard<'_, Vec<Arc<ColumnFamily>>> {
+        // Unwrap is OK as the lock is never held while panicking
+        self.column_families.read().unwrap()
     }
 
-    async fn maybe_flush_memtable(&mut self) -> Result<()> {
-        if self.main_table.len() >= self.max_memtable_size {
-            self.flush_memtable().await?;
+    /// The default column family, or [`Error::Closed`] once the database is closed.
+    fn default_cf(&self) -> Result<Arc<ColumnFamily>> {
+        self.check_open()?;
+        Ok(self.column_families()[0].clone())
+    }
+
+    /// Like [`BaumDb::default_cf`], but for the column family of the handle.
+    fn column_family(
+        &self,
+        handle: &ColumnFamilyHandle,
+    ) -> Result<Arc<ColumnFamily>> {
+        self.check_open()?;
+        self.column_families()
+            .get(handle.id())
+            .filter(|column_family| column_family.handle() == handle)
+            .cloned()
+            .ok_or_else(|| {
+                Error::InvalidArgument(format!("Unknown column family {:?}", handle.name()))
+            })
+    }
+
+    fn check_open(&self) -> Result<()> {
+        if self.closed.load(atomic::Ordering::Acquire) {
+            return Err(Error::Closed);
         }
         Ok(())
     }
 
-    async fn flush_memtable(&mut self) -> Result<()> {
-        // Can be safely reset because it only contains data that has already been flushed to disk
-        let mut previous_memtable = Default::default();
-        mem::swap(&mut previous_memtable, &mut self.main_table);
-        self.secondary_table = previous_memtable.clone().into();
-        self.file_handler.flush(previous_memtable).await?;
+    /// The sequence number of the last write visible to readers.
+    fn last_seq(&self) -> SequenceNumber {
+        self.last_seq.load(atomic::Ordering::Acquire)
+    }
+
+    /// Waits until no other write is applied, see [`BaumDb::write_locked`].
+    pub(crate) async fn lock_writes(&self) -> MutexGuard<'_, ()> {
+        self.write_lock.lock().await
+    }
+
+    /// Like [`DB::write`], but for callers already holding the write lock, which keeps other
+    /// writes from changing the state of the database the batch is based on.
+    pub(crate) async fn write_locked(
+        &self,
+        writer: &MutexGuard<'_, ()>,
+        batch: WriteBatch,
+    ) -> Result<()> {
+        let column_family = self.default_cf()?;
+        self.apply_batch(writer, &column_family, batch).await
+    }
+
+    /// Applies all writes of the batch atomically to the column family.
+    async fn write_to(
+        &self,
+        column_family: &ColumnFamily,
+        batch: WriteBatch,
+    ) -> Result<()> {
+        let writer = self.lock_writes().await;
+        self.apply_batch(&writer, column_family, batch).await
+    }
+
+    async fn apply_batch(
+        &self,
+        _writer: &MutexGuard<'_, ()>,
+        column_family: &ColumnFamily,
+        batch: WriteBatch,
+    ) -> Result<()> {
+        // Checked again as the database may have been closed while waiting for the lock
+        self.check_open()?;
+        self.background_error.check()?;
+        if batch.is_empty() {
+            return Ok(());
+        }
+        if batch.has_merges() && self.options.merge_operator.is_none() {
+            return Err(Error::InvalidArgument(
+                "Cannot merge without a merge operator".to_string(),
+            ));
+        }
+        if batch.has_empty_ranges() {
+            return Err(Error::InvalidArgument(
+                "The start of a range delete must be smaller than its end".to_string(),
+            ));
+        }
+        let last_seq = self.last_seq();
+        let entries = batch.into_entries(last_seq + 1);
+        let n_entries = entries.len() as SequenceNumber;
+        column_family.write(entries).await?;
+        self.last_seq
+            .store(last_seq + n_entries, atomic::Ordering::Release);
+        // Only checked once the whole batch is applied so that it is never split across a flush
+        let flushed = column_family
+            .maybe_flush_memtable(
+                &self.file_handler,
+                &self.snapshots.live(),
+                self.options.merge_operator.as_deref(),
+            )
+            .await;
+        // The batch is logged and visible already, so only the following writes fail until the
+        // memtable is flushed
+        if let Err(error) = flushed {
+            self.background_error.record(error);
+        }
+        Ok(())
+    }
+
+    /// The approximate memory footprint in bytes of the memtables of the column family, including
+    /// one that is currently being flushed.
+    ///
+    /// The memtable is flushed once this reaches [`Options::write_buffer_size`], or earlier if it
+    /// holds [`Options::max_memtable_size`] entries.
+    pub fn memtable_size(
+        &self,
+        column_family: &ColumnFamilyHandle,
+    ) -> Result<usize> {
+        Ok(self.column_family(column_family)?.memtable_size())
+    }
+
+    /// The error of the last background flush or compaction that failed, if any.
+    /// While it is set, reads still succeed but writes fail with [`Error::BackgroundError`].
+    pub fn background_error(&self) -> Option<Arc<Error>> {
+        self.background_error.get()
+    }
+
+    /// Clears the background error once its cause, e.g. a full disk, is fixed, so that writes
+    /// succeed again.
+    ///
+    /// Memtables whose flush failed are flushed again first. If that fails, the new error is
+    /// recorded and returned instead.
+    pub async fn clear_background_error(&self) -> Result<()> {
+        let _writer = self.lock_writes().await;
+        self.check_open()?;
+        self.background_error.clear();
+        let column_families = self.column_families().clone();
+        for column_family in column_families {
+            let flushed = column_family
+                .retry_flush(
+                    &self.file_handler,
+                    &self.snapshots.live(),
+                    self.options.merge_operator.as_deref(),
+                )
+                .await;
+            if let Err(error) = flushed {
+                return Err(Error::BackgroundError(self.background_error.record(error)));
+            }
+        }
         Ok(())
     }
+
+    /// Gets the newest value of the key in the column family that is visible at the snapshot.
+    async fn get_at(
+        &self,
+        column_family: &ColumnFamily,
+        key: &[u8],
+        snapshot: SequenceNumber,
+    ) -> Result<Option<Bytes>> {
+        let versions = column_family.get_versions_at(key, snapshot).await?;
+        resolve_versions(
+            key,
+            versions,
+            self.options.merge_operator.as_deref(),
+            current_timestamp(),
+        )
+    }
+
+    /// The sequence number of the last write to the key in the default column family, if it was
+    /// ever written.
+    pub(crate) async fn latest_seq(
+        &self,
+        key: &[u8],
+    ) -> Result<Option<SequenceNumber>> {
+        Ok(self
+            .default_cf()?
+            .get_versions_at(key, SequenceNumber::MAX)
+            .await?
+            .first()
+            .map(MemValue::seq))
+    }
+
+    async fn scan_at(
+        &self,
+        column_family: &ColumnFamily,
+        range: KeyBounds,
+        snapshot: SequenceNumber,
+    ) -> Result<DbIterator> {
+        if is_empty_range(&range) {
+            return Ok(DbIterator::new(vec![], vec![], snapshot, None));
+        }
+        let (runs, range_tombstones) = column_family.scan(&range).await?;
+        Ok(DbIterator::new(
+            runs,
+            range_tombstones,
+            snapshot,
+            self.options.merge_operator.clone(),
+        ))
+    }
+
+    /// Closes the database, which is shared by all clones of the handle.
+    ///
+    /// The memtables are flushed and the background tasks are stopped once the pending flushes and
+    /// compactions are done, so that the database can safely be reopened afterwards. All later
+    /// calls fail with [`Error::Closed`], except for closing again, which has no effect.
+    ///
+    /// The memtables are not flushed while a [background error](BaumDb::background_error) is set,
+    /// which is returned instead. Their writes are still recovered from the write-ahead logs.
+    pub async fn close(&self) -> Result<()> {
+        let _writer = self.lock_writes().await;
+        if self.closed.swap(true, atomic::Ordering::AcqRel) {
+            return Ok(());
+        }
+        let mut flushed = self.background_error.check();
+        if flushed.is_ok() {
+            let column_families = self.column_families().clone();
+            for column_family in column_families {
+                flushed = column_family
+                    .flush(
+                        &self.file_handler,
+                        &self.snapshots.live(),
+                        self.options.merge_operator.as_deref(),
+                    )
+                    .await;
+                if flushed.is_err() {
+                    break;
+                }
+            }
+        }
+        self.file_handler.close().await;
+        flushed
+    }
+
+    /// Begins an optimistic transaction reading from the current state of the database.
+    pub fn begin_transaction(&self) -> Transaction {
+        Transaction::new(self.snapshot())
+    }
+}
+
+fn column_family_path(
+    db_path: &Path,
+    name: &str,
+) -> PathBuf {
+    db_path.join(COLUMN_FAMILIES_DIR).join(name)
+}
+
+/// The names of the column families stored in the database directory besides the default one,
+/// in alphabetical order.
+async fn existing_column_family_names(db_path: &Path) -> Result<Vec<String>> {
+    let column_families_path = db_path.join(COLUMN_FAMILIES_DIR);
+    if !column_families_path.exists() {
+        return Ok(vec![]);
+    }
+    let mut names = Vec::new();
+    let mut entries = read_dir(column_families_path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(name) = entry.file_name().to_str() {
+            if entry.file_type().await?.is_dir() && validate_column_family_name(name).is_ok() {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
 }
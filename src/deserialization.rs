@@ -1,62 +1,194 @@
 use std::io::Cursor;
 use std::io::Read;
+use std::path::Path;
 
-use anyhow::anyhow;
-use anyhow::Result;
 use byteorder::BigEndian;
 use byteorder::ReadBytesExt;
+use bytes::Bytes;
+use flate2::read::GzDecoder;
 
+use crate::checksum::checksum;
+use crate::checksum::verify_checksum;
+use crate::checksum::CHECKSUM_LEN;
+use crate::error::CorruptionError;
+use crate::error::Error;
+use crate::error::Result;
 use crate::memtable::MemValue;
+use crate::memtable::WriteEntry;
+use crate::range_tombstone::RangeTombstone;
 
 /// A helper struct defining a simple key-value pair.
 #[derive(Debug)]
 pub(crate) struct KeyValue {
-    pub key: String,
+    pub key: Bytes,
     pub value: MemValue,
 }
 
 /// A helper struct defining a the offset a key can be found at in a file.
 #[derive(Debug)]
 pub(crate) struct KeyOffset {
-    pub key: String,
+    pub key: Bytes,
     pub offset: u64,
 }
 
 /// Reads a key, value pair from the reader.
-pub(crate) fn read_key_value(reader: &mut Cursor<Vec<u8>>) -> Result<KeyValue> {
-    let key_len = reader.read_u64::<BigEndian>()? as usize;
-    let mut buf: Vec<u8> = vec![0; key_len];
-    reader.read_exact(&mut buf)?;
-    let key = String::from_utf8(buf)?;
-    let value_type = reader.read_u8()?;
-    match value_type {
-        0 => Ok(KeyValue {
-            key,
-            value: MemValue::Delete,
-        }),
-        1 => {
-            let value_len = reader.read_u64::<BigEndian>()? as usize;
-            let mut buf: Vec<u8> = vec![0; value_len];
-            reader.read_exact(&mut buf)?;
-            let value = String::from_utf8(buf)?;
-            Ok(KeyValue {
-                key,
-                value: MemValue::Put(value),
-            })
+pub(crate) fn read_key_value<R: Read>(reader: &mut R) -> Result<KeyValue> {
+    match read_entry(reader)? {
+        WriteEntry::Version(key, value) => Ok(KeyValue { key, value }),
+        WriteEntry::RangeTombstone(_) => {
+            Err(Error::InvalidData("Unexpected range tombstone".to_string()))
         }
-        _ => Err(anyhow!("Wrong value type byte: {value_type}")),
     }
 }
 
+/// Reads a single write of a batch, i.e. a version of a key or a range tombstone, from the reader.
+pub(crate) fn read_entry<R: Read>(reader: &mut R) -> Result<WriteEntry> {
+    let key = read_bytes(reader)?;
+    let seq = reader.read_u64::<BigEndian>()?;
+    let value_type = reader.read_u8()?;
+    let value = match value_type {
+        0 => MemValue::Delete { seq },
+        1 => MemValue::Put {
+            seq,
+            value: read_bytes(reader)?,
+            expires_at: None,
+        },
+        3 => {
+            let expires_at = reader.read_u64::<BigEndian>()?;
+            MemValue::Put {
+                seq,
+                value: read_bytes(reader)?,
+                expires_at: Some(expires_at),
+            }
+        }
+        2 => MemValue::Merge {
+            seq,
+            operand: read_bytes(reader)?,
+        },
+        4 => {
+            return Ok(WriteEntry::RangeTombstone(RangeTombstone {
+                start: key,
+                end: read_bytes(reader)?,
+                seq,
+            }))
+        }
+        _ => {
+            return Err(Error::InvalidData(format!(
+                "Wrong value type byte: {value_type}"
+            )))
+        }
+    };
+    Ok(WriteEntry::Version(key, value))
+}
+
 pub(crate) fn read_key_offset(buffer: &mut Cursor<Vec<u8>>) -> Result<KeyOffset> {
-    let key_len = buffer.read_u64::<BigEndian>()? as usize;
-    let mut buf: Vec<u8> = vec![0; key_len];
-    buffer.read_exact(&mut buf)?;
-    let key = String::from_utf8(buf)?;
+    let key = read_bytes(buffer)?;
     let offset = buffer.read_u64::<BigEndian>()?;
     Ok(KeyOffset { key, offset })
 }
 
+/// Reads a length-prefixed byte string from the reader.
+pub(crate) fn read_bytes<R: Read>(reader: &mut R) -> Result<Bytes> {
+    let len = reader.read_u64::<BigEndian>()? as usize;
+    let mut buf: Vec<u8> = vec![0; len];
+    reader.read_exact(&mut buf)?;
+    Ok(Bytes::from(buf))
+}
+
+/// Verifies and decompresses the raw data block found at `offset` in the file at `path`.
+pub(crate) fn decode_block(
+    raw_block: &[u8],
+    expected_checksum: u32,
+    path: &Path,
+    offset: u64,
+) -> Result<Vec<u8>> {
+    verify_checksum(raw_block, expected_checksum, path, offset)?;
+    let mut decoder = GzDecoder::new(raw_block);
+    // The vec will very likely end up larger than the raw block,
+    // but that's the best we know at this point and it'll save some reallocations.
+    let mut decompressed_block = Vec::with_capacity(raw_block.len());
+    decoder.read_to_end(&mut decompressed_block)?;
+    Ok(decompressed_block)
+}
+
+/// An iterator over the records of an append-only log written with `write_record`.
+#[derive(Debug)]
+pub(crate) struct Records<'a> {
+    bytes: &'a [u8],
+    path: &'a Path,
+    offset: usize,
+    torn_offset: Option<u64>,
+}
+
+/// Reads the records from the bytes of the append-only log at `path`.
+/// A torn record at the end of the log, i.e. one that was only partially written, ends the records
+/// and is reported by [`Records::torn_offset`]. Any other record that does not match its checksum
+/// is returned as [`Error::Corruption`], after which reading stops, as the length of any record
+/// after it cannot be trusted either.
+pub(crate) fn read_records<'a>(
+    bytes: &'a [u8],
+    path: &'a Path,
+) -> Records<'a> {
+    Records {
+        bytes,
+        path,
+        offset: 0,
+        torn_offset: None,
+    }
+}
+
+/// The number of bytes preceding the payload of a record: its length and checksum.
+const RECORD_HEADER_LEN: usize = 8 + CHECKSUM_LEN;
+
+impl Records<'_> {
+    /// The offset of the torn record the log ends in, once all records were read.
+    pub(crate) fn torn_offset(&self) -> Option<u64> {
+        self.torn_offset
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<&'a [u8]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = &self.bytes[self.offset..];
+        if bytes.is_empty() {
+            return None;
+        }
+        let offset = self.offset as u64;
+        let Some((header, rest)) = bytes.split_at_checked(RECORD_HEADER_LEN) else {
+            // The last write did not fully make it to disk and was never acknowledged.
+            self.offset = self.bytes.len();
+            self.torn_offset = Some(offset);
+            return None;
+        };
+        let (len_bytes, checksum_bytes) = header.split_at(8);
+        // Unwraps are OK as the slices have exactly 8 and `CHECKSUM_LEN` bytes
+        let record_len = u64::from_be_bytes(len_bytes.try_into().unwrap());
+        let expected_checksum = u32::from_be_bytes(checksum_bytes.try_into().unwrap());
+        let Some(record) = usize::try_from(record_len)
+            .ok()
+            .and_then(|record_len| rest.get(..record_len))
+        else {
+            self.offset = self.bytes.len();
+            self.torn_offset = Some(offset);
+            return None;
+        };
+        self.offset += RECORD_HEADER_LEN + record.len();
+        if checksum(record) != expected_checksum {
+            self.offset = self.bytes.len();
+            // Only the last record may have been torn, there is no telling where the records after
+            // any other one start
+            if record.len() == rest.len() {
+                self.torn_offset = Some(offset);
+                return None;
+            }
+            return Some(Err(CorruptionError::new(self.path, offset).into()));
+        }
+        Some(Ok(record))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -64,11 +196,12 @@ mod tests {
     #[tokio::test]
     async fn read_value_works_for_deleted_key() {
         let key = "foo";
-        let value = MemValue::Delete;
+        let value = MemValue::delete(7);
 
         let mut bytes = vec![];
         bytes.extend(key.len().to_be_bytes());
         bytes.extend(key.as_bytes());
+        bytes.extend(7u64.to_be_bytes());
         // Delete is encoded as 0
         bytes.push(0);
 
@@ -85,11 +218,12 @@ mod tests {
     async fn read_value_works_for_put_key() {
         let key = "foo";
         let value_inner = "bar".to_string();
-        let value = MemValue::Put(value_inner.clone());
+        let value = MemValue::put(7, &value_inner);
 
         let mut bytes = vec![];
         bytes.extend(key.len().to_be_bytes());
         bytes.extend(key.as_bytes());
+        bytes.extend(7u64.to_be_bytes());
         // Put is encoded as 1
         bytes.push(1);
         bytes.extend(value_inner.len().to_be_bytes());
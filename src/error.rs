@@ -0,0 +1,198 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use bytes::Bytes;
+
+/// The result type returned by the database.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The errors returned by the database.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading or writing a file failed.
+    /// Retrying may succeed once the cause, e.g. a full disk, is resolved.
+    Io(io::Error),
+    /// Data read from disk does not match its checksum.
+    Corruption(CorruptionError),
+    /// Data read from disk could not be decoded even though its checksum matches, e.g. because it
+    /// was written by an incompatible version.
+    InvalidData(String),
+    /// The arguments of the call are invalid, e.g. an unknown column family or a merge without a
+    /// merge operator.
+    InvalidArgument(String),
+    /// A transaction could not be committed, retrying it may succeed.
+    TransactionConflict(TransactionConflictError),
+    /// The database was closed, so no more calls can succeed.
+    Closed,
+    /// A background flush or compaction failed with the wrapped error.
+    BackgroundError(Arc<Error>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "I/O error: {error}"),
+            Self::Corruption(error) => error.fmt(f),
+            Self::InvalidData(message) => write!(f, "Invalid data: {message}"),
+            Self::InvalidArgument(message) => write!(f, "Invalid argument: {message}"),
+            Self::TransactionConflict(error) => error.fmt(f),
+            Self::Closed => write!(f, "The database is closed"),
+            Self::BackgroundError(error) => write!(f, "Background error: {error}"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            Self::Corruption(error) => Some(error),
+            Self::TransactionConflict(error) => Some(error),
+            Self::BackgroundError(error) => Some(error.as_ref()),
+            Self::InvalidData(_) | Self::InvalidArgument(_) | Self::Closed => None,
+        }
+    }
+}
+
+/// The error of the last background flush or compaction that failed, until it is cleared.
+/// While it is set, the database only serves reads.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BackgroundErrorState(Arc<Mutex<Option<Arc<Error>>>>);
+
+impl BackgroundErrorState {
+    /// Records the error, replacing any previous one.
+    pub(crate) fn record(
+        &self,
+        error: Error,
+    ) -> Arc<Error> {
+        let error = match error {
+            Error::BackgroundError(error) => error,
+            error => Arc::new(error),
+        };
+        // Unwrap is OK as the lock is never held while panicking
+        *self.0.lock().unwrap() = Some(error.clone());
+        error
+    }
+
+    pub(crate) fn get(&self) -> Option<Arc<Error>> {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub(crate) fn clear(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+
+    /// Fails with [`Error::BackgroundError`] while an error is recorded.
+    pub(crate) fn check(&self) -> Result<()> {
+        match self.get() {
+            Some(error) => Err(Error::BackgroundError(error)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<CorruptionError> for Error {
+    fn from(error: CorruptionError) -> Self {
+        Self::Corruption(error)
+    }
+}
+
+impl From<TransactionConflictError> for Error {
+    fn from(error: TransactionConflictError) -> Self {
+        Self::TransactionConflict(error)
+    }
+}
+
+/// Signals that data read from disk does not match the checksum stored alongside it, or that a
+/// log record was cut short before the end of the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptionError {
+    path: PathBuf,
+    offset: u64,
+}
+
+impl CorruptionError {
+    pub(crate) fn new(
+        path: &Path,
+        offset: u64,
+    ) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            offset,
+        }
+    }
+
+    /// The path of the corrupted file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The offset of the corrupted data within the file.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+impl fmt::Display for CorruptionError {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        write!(
+            f,
+            "Corrupted data in {} at offset {}",
+            self.path.display(),
+            self.offset
+        )
+    }
+}
+
+impl error::Error for CorruptionError {}
+
+/// Signals that a transaction could not be committed as a key it read was modified after the
+/// transaction began.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionConflictError {
+    key: Bytes,
+}
+
+impl TransactionConflictError {
+    pub(crate) fn new(key: Bytes) -> Self {
+        Self { key }
+    }
+
+    /// The key that was modified concurrently.
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+}
+
+impl fmt::Display for TransactionConflictError {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        write!(
+            f,
+            "Transaction conflict on key {}",
+            String::from_utf8_lossy(&self.key)
+        )
+    }
+}
+
+impl error::Error for TransactionConflictError {}
@@ -1,26 +1,55 @@
 use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::ops::Bound;
 
-use anyhow::Result;
 use async_trait::async_trait;
 
+use crate::bloom_filter::BloomFilter;
+use crate::bloom_filter::DefaultBloomFilter;
+use crate::error::Result;
+use crate::file_handling::file_bundle::FileBundle;
 use crate::file_handling::file_bundle::FileBundleHandle;
 use crate::file_handling::file_bundle::FileBundleId;
 use crate::file_handling::file_bundle::FileBundles;
+use crate::file_handling::file_bundle::KeyRange;
 use crate::file_handling::file_bundle::Level;
 use crate::file_handling::file_bundle::ShouldCompact;
-use crate::file_handling::flushing::flush;
+use crate::file_handling::flushing::write_file_bundle;
 use crate::file_handling::DataHandling;
+use crate::file_handling::SstFileBundle;
+use crate::iterator::KeyBounds;
+use crate::memtable::current_timestamp;
 use crate::memtable::MemTable;
+use crate::memtable::MemTableGet;
+use crate::memtable::MemTableWrite;
 use crate::memtable::MemValue;
+use crate::merge_operator::MergeOperator;
+use crate::range_tombstone::read_range_tombstones;
+use crate::range_tombstone::RangeTombstone;
+use crate::snapshot::SnapshotList;
 
 #[async_trait]
 pub(super) trait Compaction {
-    async fn compact(&self) -> Result<()>;
+    /// Compacts the levels that are over their threshold, retaining the versions that are still
+    /// visible to the live snapshots.
+    /// Merge operands are applied to their base value if the merge operator is given, and expired
+    /// puts are dropped once they do not need to shadow older versions anymore.
+    /// Versions deleted by range tombstones are dropped as well, skipping the bundles whose keys
+    /// are all deleted without reading them.
+    async fn compact(
+        &self,
+        snapshots: &SnapshotList,
+        merge_operator: Option<&dyn MergeOperator>,
+    ) -> Result<()>;
 }
 
 #[async_trait]
 impl Compaction for FileBundles {
-    async fn compact(&self) -> Result<()> {
+    async fn compact(
+        &self,
+        snapshots: &SnapshotList,
+        merge_operator: Option<&dyn MergeOperator>,
+    ) -> Result<()> {
         let mut level_to_compact = Level::L0;
         loop {
             let Some(next_level) = level_to_compact.next_level() else {
@@ -35,6 +64,8 @@ impl Compaction for FileBundles {
                 Level::L1 => read_lock.l1.clone(),
                 Level::L2 => unreachable!(),
             };
+            // Only compaction adds bundles to the last level, so this stays up to date
+            let bottom_level_bundles = read_lock.l2.clone();
             drop(read_lock);
 
             if bundles.is_empty() {
@@ -44,38 +75,66 @@ impl Compaction for FileBundles {
             // Invariant is that they are sorted in order, reversing then is oldest to newest.
             bundles.make_contiguous().reverse();
 
-            let mut iter = bundles.iter();
-            // Unwrap is OK here because at least one element must exist as checked above
-            let oldest_bundle = iter.next().unwrap();
-            // The oldest table serves as the table we merge newer data into
-            let mut merger_table = MemTable::try_from_file(oldest_bundle.main_data_file_path())
-                .await?
-                .into_inner();
-            compacted_bundle_ids.insert(oldest_bundle.id());
-
-            for newer_bundle in iter {
-                let newer_table =
-                    MemTable::try_from_file(newer_bundle.main_data_file_path()).await?;
-                compacted_bundle_ids.insert(newer_bundle.id());
-                for (key, value) in newer_table.into_iter() {
-                    match value {
-                        MemValue::Put(_) => {
-                            merger_table.insert(key, value);
-                        }
-                        MemValue::Delete => {
-                            merger_table.remove(&key);
-                        }
-                    }
+            let mut range_tombstones = Vec::with_capacity(bundles.len());
+            for bundle in bundles.iter() {
+                range_tombstones.push(read_range_tombstones(&SstFileBundle::from(bundle)).await?);
+            }
+            let live_snapshots = snapshots.live();
+            // The table the data of all bundles is merged into, from oldest to newest
+            let mut merger_table = MemTable::default();
+            for (bundle, bundle_range_tombstones) in bundles.iter().zip(&range_tombstones) {
+                compacted_bundle_ids.insert(bundle.id());
+                // Once all keys of the bundle are deleted by a newer range tombstone that no
+                // snapshot predates, none of its data can be read anymore. This includes its own
+                // range tombstones, which only delete what the newer one deletes as well.
+                let is_deleted = range_tombstones.iter().flatten().any(|tombstone| {
+                    deletes_bundle(tombstone, bundle)
+                        && live_snapshots.range(..tombstone.seq).next().is_none()
+                });
+                if is_deleted {
+                    continue;
+                }
+                let table = MemTable::try_from_file(bundle.main_data_file_path()).await?;
+                // Tombstones are included as they must keep shadowing older values of their key in
+                // lower levels.
+                merger_table.extend(table.into_entries());
+                for tombstone in bundle_range_tombstones {
+                    merger_table.insert_range_tombstone(tombstone.clone());
                 }
             }
+            // Only keys stored in the older bundles of the last level may have older data
+            let older_data = if next_level.next_level().is_none() {
+                Some(OlderData::load(&bottom_level_bundles).await?)
+            } else {
+                None
+            };
+            // Expired puts keep shadowing older versions of their key as tombstones
+            merger_table.expire_versions(current_timestamp());
+            if let Some(merge_operator) = merge_operator {
+                // Must happen before pruning versions that operands are applied to
+                merger_table.resolve_merge_operands(merge_operator, |key| {
+                    older_data
+                        .as_ref()
+                        .is_some_and(|older_data| !older_data.may_contain(key))
+                });
+            }
+            // Only the newest version of a key and the ones still read by snapshots are kept
+            merger_table.retain_visible_versions(&live_snapshots);
 
-            if merger_table.is_empty() {
-                return Ok(());
+            if let Some(older_data) = &older_data {
+                drop_obsolete_tombstones(&mut merger_table, older_data);
             }
 
-            let should_compact =
-                flush(MemTable::from(merger_table), self.clone(), next_level).await?;
-            self.clone().remove_bundles(&compacted_bundle_ids).await;
+            let uncommitted_bundle = if merger_table.is_empty() {
+                None
+            } else {
+                Some(write_file_bundle(merger_table, self, next_level).await?)
+            };
+            // Swapping in the compacted bundle in a single step ensures that the compacted data
+            // is never visible twice or not at all.
+            let should_compact = self
+                .replace_bundles(&compacted_bundle_ids, uncommitted_bundle)
+                .await?;
             if should_compact == ShouldCompact::Yes {
                 level_to_compact = next_level
             } else {
@@ -84,3 +143,448 @@ impl Compaction for FileBundles {
         }
     }
 }
+
+/// The bloom filters of the bundles holding data older than the compacted bundles.
+struct OlderData<'a>(Vec<(&'a KeyRange, DefaultBloomFilter)>);
+
+impl<'a> OlderData<'a> {
+    async fn load(older_bundles: &'a VecDeque<FileBundle>) -> Result<Self> {
+        let mut bloom_filters = Vec::with_capacity(older_bundles.len());
+        for bundle in older_bundles.iter() {
+            let bloom_filter =
+                DefaultBloomFilter::try_from_file(bundle.bloom_filter_file_path()).await?;
+            bloom_filters.push((bundle.key_range(), bloom_filter));
+        }
+        Ok(Self(bloom_filters))
+    }
+
+    fn may_contain(
+        &self,
+        key: &[u8],
+    ) -> bool {
+        self.0.iter().any(|(key_range, bloom_filter)| {
+            key_range.contains(key) && bloom_filter.may_contain_key(key)
+        })
+    }
+
+    /// Whether any key of the range tombstone may be stored in the older bundles.
+    fn may_overlap(
+        &self,
+        tombstone: &RangeTombstone,
+    ) -> bool {
+        self.0.iter().any(|(key_range, _)| {
+            key_range.smallest < tombstone.end && tombstone.start <= key_range.largest
+        })
+    }
+}
+
+/// Whether the range tombstone deletes all data of the bundle.
+fn deletes_bundle(
+    tombstone: &RangeTombstone,
+    bundle: &FileBundle,
+) -> bool {
+    let key_range = bundle.key_range();
+    tombstone.seq > bundle.largest_seq()
+        && tombstone.start <= key_range.smallest
+        && key_range.largest < tombstone.end
+}
+
+/// Drops the oldest tombstones of keys that are not stored in any of the older bundles.
+/// Such tombstones do not shadow any data anymore when compacting into the last level.
+/// The same goes for range tombstones that neither delete older data nor versions of the table
+/// still kept for snapshots.
+fn drop_obsolete_tombstones(
+    table: &mut MemTable,
+    older_data: &OlderData<'_>,
+) {
+    table.retain_versions(|key, versions| {
+        let may_shadow_older_data = older_data.may_contain(key);
+        // Newer tombstones may still shadow older versions of the key read by snapshots
+        while !may_shadow_older_data && matches!(versions.last(), Some(MemValue::Delete { .. })) {
+            versions.pop();
+        }
+    });
+    let is_needed = table
+        .range_tombstones()
+        .iter()
+        .map(|tombstone| {
+            let range: KeyBounds = (
+                Bound::Included(tombstone.start.clone()),
+                Bound::Excluded(tombstone.end.clone()),
+            );
+            older_data.may_overlap(tombstone)
+                || table
+                    .scan(&range)
+                    .any(|(key, value)| tombstone.covers(key, value.seq()))
+        })
+        .collect::<Vec<_>>();
+    let mut is_needed = is_needed.into_iter();
+    // Unwrap is OK as there is one entry per range tombstone
+    table.retain_range_tombstones(|_| is_needed.next().unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::atomic::AtomicU64;
+    use std::sync::atomic::Ordering;
+
+    use bytes::Bytes;
+    use tokio::fs::create_dir_all;
+    use tokio::fs::remove_dir_all;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::file_handling::flushing::flush;
+    use crate::memtable::SequenceNumber;
+    use crate::merge_operator::tests::AppendOperator;
+    use crate::options::Options;
+
+    static NEXT_SEQ: AtomicU64 = AtomicU64::new(1);
+
+    /// Creates an empty directory for the test and the bundles stored in it.
+    async fn prepare_bundles() -> (PathBuf, FileBundles) {
+        let path = PathBuf::from(format!("./test-logs/{:?}", Uuid::new_v4()));
+        create_dir_all(&path).await.unwrap();
+        let bundles = FileBundles::recover(path.clone(), Options::default())
+            .await
+            .unwrap();
+        (path, bundles)
+    }
+
+    async fn clean_up(path: PathBuf) {
+        let _ = remove_dir_all(path).await;
+    }
+
+    /// Flushes the entries to a new bundle, tagging them with increasing sequence numbers.
+    async fn flush_entries(
+        bundles: &FileBundles,
+        entries: &[(&str, Option<&str>)],
+        level: Level,
+    ) {
+        let mut table = MemTable::default();
+        for (key, value) in entries {
+            let seq = NEXT_SEQ.fetch_add(1, Ordering::Relaxed);
+            let value = match value {
+                Some(value) => MemValue::put(seq, value),
+                None => MemValue::delete(seq),
+            };
+            table.insert(Bytes::copy_from_slice(key.as_bytes()), value);
+        }
+        flush(table, bundles.clone(), level).await.unwrap();
+    }
+
+    /// Looks up the key as of the snapshot in the bundles from newest to oldest.
+    async fn lookup_at(
+        bundles: &FileBundles,
+        key: &str,
+        snapshot: SequenceNumber,
+    ) -> Option<MemValue> {
+        let arc = bundles.inner();
+        let lock = arc.read().await;
+        for bundle in lock.iter() {
+            let table = MemTable::try_from_file(bundle.main_data_file_path)
+                .await
+                .unwrap();
+            if let Some(value) = table
+                .get(key.as_bytes(), snapshot)
+                .unwrap()
+                .into_iter()
+                .next()
+            {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// Looks up the newest value of the key.
+    async fn lookup(
+        bundles: &FileBundles,
+        key: &str,
+    ) -> Option<MemValue> {
+        lookup_at(bundles, key, SequenceNumber::MAX).await
+    }
+
+    #[tokio::test]
+    async fn tombstones_are_kept_when_compacting_into_l1() {
+        let (path, bundles) = prepare_bundles().await;
+
+        // The key has already reached the last level before it is deleted
+        flush_entries(&bundles, &[("key", Some("old"))], Level::L2).await;
+        flush_entries(&bundles, &[("key", None)], Level::L0).await;
+        for other_key in ["a", "b", "c"] {
+            flush_entries(&bundles, &[(other_key, Some("value"))], Level::L0).await;
+        }
+
+        bundles
+            .compact(&SnapshotList::default(), None)
+            .await
+            .unwrap();
+
+        {
+            let arc = bundles.inner();
+            let lock = arc.read().await;
+            assert!(lock.l0.is_empty());
+            assert_eq!(lock.l1.len(), 1);
+            assert_eq!(lock.l2.len(), 1);
+        }
+        assert!(matches!(
+            lookup(&bundles, "key").await,
+            Some(MemValue::Delete { .. })
+        ));
+        assert_eq!(
+            lookup(&bundles, "a")
+                .await
+                .and_then(MemValue::into_put_value),
+            Some(Bytes::from("value"))
+        );
+
+        clean_up(path).await;
+    }
+
+    #[tokio::test]
+    async fn tombstones_are_only_dropped_in_last_level_without_older_data() {
+        let (path, bundles) = prepare_bundles().await;
+
+        flush_entries(&bundles, &[("shadowed", Some("old"))], Level::L2).await;
+        flush_entries(
+            &bundles,
+            &[("shadowed", None), ("unshadowed", None)],
+            Level::L1,
+        )
+        .await;
+        for idx in 0..7 {
+            let key = format!("key-{idx}");
+            flush_entries(&bundles, &[(&key, Some("value"))], Level::L1).await;
+        }
+        // Pushes L1 over its threshold once compacted
+        flush_entries(&bundles, &[("key-7", Some("value"))], Level::L0).await;
+
+        bundles
+            .compact(&SnapshotList::default(), None)
+            .await
+            .unwrap();
+
+        {
+            let arc = bundles.inner();
+            let lock = arc.read().await;
+            assert!(lock.l0.is_empty());
+            assert!(lock.l1.is_empty());
+            assert_eq!(lock.l2.len(), 2);
+            let compacted_table = MemTable::try_from_file(lock.l2[0].main_data_file_path())
+                .await
+                .unwrap();
+            assert!(matches!(
+                compacted_table
+                    .get(b"shadowed", SequenceNumber::MAX)
+                    .unwrap()[..],
+                [MemValue::Delete { .. }]
+            ));
+            assert!(compacted_table
+                .get(b"unshadowed", SequenceNumber::MAX)
+                .unwrap()
+                .is_empty());
+        }
+        assert!(matches!(
+            lookup(&bundles, "shadowed").await,
+            Some(MemValue::Delete { .. })
+        ));
+
+        clean_up(path).await;
+    }
+
+    #[tokio::test]
+    async fn merge_operands_are_applied_to_their_base_value() {
+        let (path, bundles) = prepare_bundles().await;
+
+        flush_entries(&bundles, &[("based", Some("a"))], Level::L0).await;
+        for operand in ["b", "c", "d"] {
+            let mut table = MemTable::default();
+            for key in ["based", "unbased"] {
+                let seq = NEXT_SEQ.fetch_add(1, Ordering::Relaxed);
+                table.insert(Bytes::from(key), MemValue::merge(seq, operand));
+            }
+            flush(table, bundles.clone(), Level::L0).await.unwrap();
+        }
+
+        bundles
+            .compact(&SnapshotList::default(), Some(&AppendOperator))
+            .await
+            .unwrap();
+
+        let arc = bundles.inner();
+        let lock = arc.read().await;
+        assert!(lock.l0.is_empty());
+        let compacted_table = MemTable::try_from_file(lock.l1[0].main_data_file_path())
+            .await
+            .unwrap();
+        drop(lock);
+        assert_eq!(
+            compacted_table
+                .get(b"based", SequenceNumber::MAX)
+                .unwrap()
+                .into_iter()
+                .map(MemValue::into_put_value)
+                .collect::<Vec<_>>(),
+            vec![Some(Bytes::from("a,b,c,d"))]
+        );
+        // Older versions of the key may still be stored in the last level
+        assert_eq!(
+            compacted_table
+                .get(b"unbased", SequenceNumber::MAX)
+                .unwrap()
+                .len(),
+            3
+        );
+
+        clean_up(path).await;
+    }
+
+    #[tokio::test]
+    async fn expired_puts_are_dropped_in_last_level() {
+        let (path, bundles) = prepare_bundles().await;
+
+        flush_entries(&bundles, &[("shadowed", Some("old"))], Level::L2).await;
+        let mut table = MemTable::default();
+        for (key, expires_at) in [("expired", 1), ("shadowed", 1), ("unexpired", u64::MAX)] {
+            let seq = NEXT_SEQ.fetch_add(1, Ordering::Relaxed);
+            let value = MemValue::Put {
+                seq,
+                value: Bytes::from("value"),
+                expires_at: Some(expires_at),
+            };
+            table.insert(Bytes::from(key), value);
+        }
+        flush(table, bundles.clone(), Level::L1).await.unwrap();
+        for idx in 0..7 {
+            let key = format!("key-{idx}");
+            flush_entries(&bundles, &[(&key, Some("value"))], Level::L1).await;
+        }
+        // Pushes L1 over its threshold once compacted
+        flush_entries(&bundles, &[("key-7", Some("value"))], Level::L0).await;
+
+        bundles
+            .compact(&SnapshotList::default(), None)
+            .await
+            .unwrap();
+
+        let arc = bundles.inner();
+        let lock = arc.read().await;
+        assert!(lock.l1.is_empty());
+        let compacted_table = MemTable::try_from_file(lock.l2[0].main_data_file_path())
+            .await
+            .unwrap();
+        drop(lock);
+        assert!(compacted_table
+            .get(b"expired", SequenceNumber::MAX)
+            .unwrap()
+            .is_empty());
+        // The older value must stay hidden
+        assert!(matches!(
+            compacted_table
+                .get(b"shadowed", SequenceNumber::MAX)
+                .unwrap()[..],
+            [MemValue::Delete { .. }]
+        ));
+        assert!(matches!(
+            compacted_table
+                .get(b"unexpired", SequenceNumber::MAX)
+                .unwrap()[..],
+            [MemValue::Put { .. }]
+        ));
+
+        clean_up(path).await;
+    }
+
+    #[tokio::test]
+    async fn bundles_deleted_by_range_tombstones_are_dropped_unread() {
+        let (path, bundles) = prepare_bundles().await;
+
+        flush_entries(
+            &bundles,
+            &[("a-1", Some("1")), ("a-2", Some("2"))],
+            Level::L0,
+        )
+        .await;
+        flush_entries(&bundles, &[("a-3", Some("3")), ("b", Some("4"))], Level::L0).await;
+        let mut table = MemTable::default();
+        table.insert_range_tombstone(RangeTombstone {
+            start: Bytes::from("a"),
+            end: Bytes::from("a-3"),
+            seq: NEXT_SEQ.fetch_add(1, Ordering::Relaxed),
+        });
+        flush(table, bundles.clone(), Level::L0).await.unwrap();
+        flush_entries(&bundles, &[("c", Some("5"))], Level::L0).await;
+        // Compaction fails if it tries to read the bundle deleted by the range tombstone
+        let deleted_bundle_path = bundles.inner().read().await.l0[3]
+            .main_data_file_path()
+            .to_path_buf();
+        tokio::fs::write(deleted_bundle_path, b"corrupt")
+            .await
+            .unwrap();
+
+        bundles
+            .compact(&SnapshotList::default(), None)
+            .await
+            .unwrap();
+
+        let arc = bundles.inner();
+        let lock = arc.read().await;
+        assert!(lock.l0.is_empty());
+        let compacted_table = MemTable::try_from_file(lock.l1[0].main_data_file_path())
+            .await
+            .unwrap();
+        let compacted_range_tombstones = read_range_tombstones(&SstFileBundle::from(&lock.l1[0]))
+            .await
+            .unwrap();
+        drop(lock);
+        assert_eq!(
+            compacted_table
+                .iter()
+                .map(|(key, _)| key.clone())
+                .collect::<Vec<_>>(),
+            vec!["a-3", "b", "c"]
+        );
+        // Older versions of the keys may still be stored in the last level
+        assert_eq!(compacted_range_tombstones.len(), 1);
+
+        clean_up(path).await;
+    }
+
+    #[tokio::test]
+    async fn versions_visible_to_live_snapshots_survive_compaction() {
+        let (path, bundles) = prepare_bundles().await;
+        let snapshots = SnapshotList::default();
+
+        flush_entries(&bundles, &[("key", Some("1"))], Level::L0).await;
+        flush_entries(&bundles, &[("key", Some("2"))], Level::L0).await;
+        let snapshot = snapshots.acquire(lookup(&bundles, "key").await.unwrap().seq());
+        flush_entries(&bundles, &[("key", Some("3"))], Level::L0).await;
+        flush_entries(&bundles, &[("key", None)], Level::L0).await;
+
+        bundles.compact(&snapshots, None).await.unwrap();
+
+        let arc = bundles.inner();
+        let lock = arc.read().await;
+        assert!(lock.l0.is_empty());
+        let compacted_table = MemTable::try_from_file(lock.l1[0].main_data_file_path())
+            .await
+            .unwrap();
+        drop(lock);
+        // The version read by the snapshot and the newest one are kept
+        assert_eq!(compacted_table.len(), 2);
+        assert_eq!(
+            lookup_at(&bundles, "key", snapshot.sequence_number())
+                .await
+                .and_then(MemValue::into_put_value),
+            Some(Bytes::from("2"))
+        );
+        assert!(matches!(
+            lookup(&bundles, "key").await,
+            Some(MemValue::Delete { .. })
+        ));
+
+        clean_up(path).await;
+    }
+}
@@ -1,17 +1,32 @@
 use std::collections::HashSet;
 use std::collections::VecDeque;
+use std::io::Read;
+use std::ops::Bound;
+use std::ops::RangeBounds;
 use std::path::Path;
 use std::path::PathBuf;
 use std::slice;
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use byteorder::BigEndian;
+use byteorder::ReadBytesExt;
+use bytes::Bytes;
+use tokio::fs::read_dir;
 use tokio::fs::remove_file;
+use tokio::sync::Mutex;
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
-// The number of file bundles per level before compaction is started
-const LEVEL_COMPACTION_THRESHOLD: usize = 4;
+use crate::deserialization::read_bytes;
+use crate::error::Error;
+use crate::error::Result;
+use crate::file_handling::manifest::Manifest;
+use crate::file_handling::manifest::VersionEdit;
+use crate::iterator::is_past_end;
+use crate::iterator::KeyBounds;
+use crate::memtable::SequenceNumber;
+use crate::options::Options;
 
 #[derive(Debug, Clone)]
 pub(crate) struct FileBundlesLevelled {
@@ -22,15 +37,125 @@ pub(crate) struct FileBundlesLevelled {
 }
 
 impl FileBundlesLevelled {
-    fn new(base_path: PathBuf) -> Self {
+    pub(super) fn new(base_path: PathBuf) -> Self {
         Self {
             base_path,
-            l0: VecDeque::with_capacity(LEVEL_COMPACTION_THRESHOLD),
-            l1: VecDeque::with_capacity(LEVEL_COMPACTION_THRESHOLD),
-            l2: VecDeque::with_capacity(LEVEL_COMPACTION_THRESHOLD),
+            l0: VecDeque::new(),
+            l1: VecDeque::new(),
+            l2: VecDeque::new(),
         }
     }
 
+    /// Applies the version edit, returning the bundles that were removed by it.
+    pub(super) fn apply(
+        &mut self,
+        edit: VersionEdit,
+    ) -> Vec<FileBundle> {
+        let VersionEdit { added, removed } = edit;
+        let mut removed_bundles = Vec::with_capacity(removed.len());
+        for level in [&mut self.l0, &mut self.l1, &mut self.l2] {
+            let mut i = 0;
+            while i < level.len() {
+                if removed.contains(&level[i].id) {
+                    // Unwrap is OK as the index was checked to be in bounds
+                    removed_bundles.push(level.remove(i).unwrap())
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        for bundle in added {
+            // Newer bundles go to the front of their level
+            self.level_mut(bundle.level).push_front(bundle);
+        }
+        removed_bundles
+    }
+
+    /// A version edit adding all live bundles, oldest first.
+    pub(super) fn snapshot(&self) -> VersionEdit {
+        let added = self
+            .l0
+            .iter()
+            .rev()
+            .chain(self.l1.iter().rev())
+            .chain(self.l2.iter().rev())
+            .cloned()
+            .collect();
+        VersionEdit {
+            added,
+            removed: Default::default(),
+        }
+    }
+
+    fn level_mut(
+        &mut self,
+        level: Level,
+    ) -> &mut VecDeque<FileBundle> {
+        match level {
+            Level::L0 => &mut self.l0,
+            Level::L1 => &mut self.l1,
+            Level::L2 => &mut self.l2,
+        }
+    }
+
+    fn should_compact(
+        &self,
+        level: Level,
+        options: &Options,
+    ) -> ShouldCompact {
+        let should_compact = match level {
+            Level::L0 => self.l0.len() >= options.l0_compaction_threshold,
+            Level::L1 => self.l1.len() >= options.l1_compaction_threshold,
+            // Never compact the last level
+            Level::L2 => false,
+        };
+        if should_compact {
+            ShouldCompact::Yes
+        } else {
+            ShouldCompact::No
+        }
+    }
+
+    /// Removes bundle files that are not referenced by any live bundle.
+    /// These are left over from flushes or compactions that were interrupted before they were
+    /// recorded in the manifest.
+    async fn remove_unreferenced_files(&self) -> Result<()> {
+        let referenced: HashSet<&Path> = self
+            .iter()
+            .flat_map(|bundle| {
+                [
+                    Some(bundle.main_data_file_path),
+                    Some(bundle.index_file_path),
+                    Some(bundle.bloom_filter_file_path),
+                    bundle.range_tombstones_file_path,
+                ]
+            })
+            .flatten()
+            .collect();
+        let mut entries = read_dir(&self.base_path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_name = entry.file_name();
+            let is_bundle_file = file_name
+                .to_str()
+                .and_then(parse_bundle_file_name)
+                .is_some();
+            if is_bundle_file && !referenced.contains(self.base_path.join(&file_name).as_path()) {
+                remove_file(entry.path()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The largest sequence number stored in any of the bundles.
+    pub(crate) fn largest_seq(&self) -> Option<SequenceNumber> {
+        self.l0
+            .iter()
+            .chain(self.l1.iter())
+            .chain(self.l2.iter())
+            .map(|bundle| bundle.largest_seq)
+            .max()
+    }
+
     pub(crate) fn iter(&self) -> Iter<'_> {
         let (i01, i02) = self.l0.as_slices();
         let (i11, i12) = self.l1.as_slices();
@@ -76,6 +201,8 @@ pub(crate) struct SstFileBundle<'a> {
     pub main_data_file_path: &'a Path,
     pub index_file_path: &'a Path,
     pub bloom_filter_file_path: &'a Path,
+    pub range_tombstones_file_path: Option<&'a Path>,
+    pub key_range: &'a KeyRange,
 }
 
 #[derive(Debug, Clone)]
@@ -84,7 +211,69 @@ pub(crate) struct FileBundle {
     main_data_file_path: PathBuf,
     index_file_path: PathBuf,
     bloom_filter_file_path: PathBuf,
+    // Only bundles holding range tombstones have a file for them
+    range_tombstones_file_path: Option<PathBuf>,
     level: Level,
+    key_range: KeyRange,
+    // The largest sequence number of any version stored in the bundle
+    largest_seq: SequenceNumber,
+}
+
+/// The smallest and largest key stored in a file bundle.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct KeyRange {
+    pub smallest: Bytes,
+    pub largest: Bytes,
+}
+
+impl KeyRange {
+    pub(crate) fn contains(
+        &self,
+        key: &[u8],
+    ) -> bool {
+        self.smallest.as_ref() <= key && key <= self.largest.as_ref()
+    }
+
+    /// Whether any key of the range may be stored in the bundle.
+    pub(crate) fn overlaps(
+        &self,
+        range: &KeyBounds,
+    ) -> bool {
+        let starts_before_largest = match range.start_bound() {
+            Bound::Included(start) => start <= &self.largest,
+            Bound::Excluded(start) => start < &self.largest,
+            Bound::Unbounded => true,
+        };
+        starts_before_largest && !is_past_end(&self.smallest, range)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum BundleFileKind {
+    Data,
+    Index,
+    Bloom,
+    RangeTombstones,
+}
+
+/// Parses file names of the form `{level}-{kind}-{number}.db`.
+fn parse_bundle_file_name(file_name: &str) -> Option<(Level, BundleFileKind, usize)> {
+    let mut parts = file_name.strip_suffix(".db")?.splitn(3, '-');
+    let level = match parts.next()? {
+        "L0" => Level::L0,
+        "L1" => Level::L1,
+        "L2" => Level::L2,
+        _ => return None,
+    };
+    let kind = match parts.next()? {
+        "data" => BundleFileKind::Data,
+        "index" => BundleFileKind::Index,
+        "bloom" => BundleFileKind::Bloom,
+        "range" => BundleFileKind::RangeTombstones,
+        _ => return None,
+    };
+    let number = parts.next()?.parse().ok()?;
+    Some((level, kind, number))
 }
 
 #[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
@@ -94,10 +283,22 @@ impl FileBundleId {
     pub(crate) fn new() -> Self {
         Self(Uuid::new_v4())
     }
+
+    pub(super) fn encode(
+        &self,
+        buffer: &mut Vec<u8>,
+    ) {
+        buffer.extend(self.0.as_bytes());
+    }
+
+    pub(super) fn decode<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut id = [0; 16];
+        reader.read_exact(&mut id)?;
+        Ok(Self(Uuid::from_bytes(id)))
+    }
 }
 
-#[derive(Debug, Copy, Clone)]
-#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
 pub(crate) enum Level {
     L0,
     L1,
@@ -114,6 +315,29 @@ impl Level {
     }
 }
 
+impl From<Level> for u8 {
+    fn from(value: Level) -> Self {
+        match value {
+            Level::L0 => 0,
+            Level::L1 => 1,
+            Level::L2 => 2,
+        }
+    }
+}
+
+impl TryFrom<u8> for Level {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Level::L0),
+            1 => Ok(Level::L1),
+            2 => Ok(Level::L2),
+            _ => Err(Error::InvalidData(format!("Wrong level byte: {value}"))),
+        }
+    }
+}
+
 impl FileBundle {
     pub(crate) fn id(&self) -> FileBundleId {
         self.id
@@ -122,6 +346,102 @@ impl FileBundle {
     pub(crate) fn main_data_file_path(&self) -> &Path {
         &self.main_data_file_path
     }
+
+    pub(crate) fn bloom_filter_file_path(&self) -> &Path {
+        &self.bloom_filter_file_path
+    }
+
+    pub(crate) fn key_range(&self) -> &KeyRange {
+        &self.key_range
+    }
+
+    pub(crate) fn largest_seq(&self) -> SequenceNumber {
+        self.largest_seq
+    }
+
+    /// Encodes the bundle for the manifest.
+    /// File paths are stored relative to the database directory.
+    pub(super) fn encode(
+        &self,
+        buffer: &mut Vec<u8>,
+    ) -> Result<()> {
+        self.id.encode(buffer);
+        buffer.push(self.level.into());
+        for path in [
+            &self.main_data_file_path,
+            &self.index_file_path,
+            &self.bloom_filter_file_path,
+        ] {
+            encode_file_name(buffer, path)?;
+        }
+        match &self.range_tombstones_file_path {
+            Some(path) => {
+                buffer.push(1);
+                encode_file_name(buffer, path)?;
+            }
+            None => buffer.push(0),
+        }
+        encode_bytes(buffer, &self.key_range.smallest);
+        encode_bytes(buffer, &self.key_range.largest);
+        buffer.extend(self.largest_seq.to_be_bytes());
+        Ok(())
+    }
+
+    /// Decodes a bundle encoded with [`FileBundle::encode`].
+    pub(super) fn decode<R: Read>(
+        reader: &mut R,
+        base_path: &Path,
+    ) -> Result<Self> {
+        let id = FileBundleId::decode(reader)?;
+        let level = Level::try_from(reader.read_u8()?)?;
+        let main_data_file_path = base_path.join(decode_string(reader)?);
+        let index_file_path = base_path.join(decode_string(reader)?);
+        let bloom_filter_file_path = base_path.join(decode_string(reader)?);
+        let range_tombstones_file_path = match reader.read_u8()? {
+            0 => None,
+            _ => Some(base_path.join(decode_string(reader)?)),
+        };
+        let key_range = KeyRange {
+            smallest: read_bytes(reader)?,
+            largest: read_bytes(reader)?,
+        };
+        let largest_seq = reader.read_u64::<BigEndian>()?;
+        Ok(Self {
+            id,
+            main_data_file_path,
+            index_file_path,
+            bloom_filter_file_path,
+            range_tombstones_file_path,
+            level,
+            key_range,
+            largest_seq,
+        })
+    }
+}
+
+fn encode_file_name(
+    buffer: &mut Vec<u8>,
+    path: &Path,
+) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| Error::InvalidData(format!("Invalid bundle file path: {path:?}")))?;
+    encode_bytes(buffer, file_name.as_bytes());
+    Ok(())
+}
+
+fn encode_bytes(
+    buffer: &mut Vec<u8>,
+    value: &[u8],
+) {
+    buffer.extend((value.len() as u64).to_be_bytes());
+    buffer.extend(value);
+}
+
+fn decode_string<R: Read>(reader: &mut R) -> Result<String> {
+    String::from_utf8(read_bytes(reader)?.to_vec())
+        .map_err(|error| Error::InvalidData(error.to_string()))
 }
 
 impl<'a> From<&'a FileBundle> for SstFileBundle<'a> {
@@ -130,19 +450,15 @@ impl<'a> From<&'a FileBundle> for SstFileBundle<'a> {
             main_data_file_path: &value.main_data_file_path,
             index_file_path: &value.index_file_path,
             bloom_filter_file_path: &value.bloom_filter_file_path,
+            range_tombstones_file_path: value.range_tombstones_file_path.as_deref(),
+            key_range: &value.key_range,
         }
     }
 }
 
 impl From<UncommittedFileBundle> for FileBundle {
     fn from(value: UncommittedFileBundle) -> Self {
-        Self {
-            id: value.0.id,
-            main_data_file_path: value.0.main_data_file_path,
-            index_file_path: value.0.index_file_path,
-            bloom_filter_file_path: value.0.bloom_filter_file_path,
-            level: value.0.level,
-        }
+        value.0
     }
 }
 
@@ -165,6 +481,10 @@ impl UncommittedFileBundle {
     pub(crate) fn bloom_filter_file_path(&self) -> &PathBuf {
         &self.0.bloom_filter_file_path
     }
+
+    pub(crate) fn range_tombstones_file_path(&self) -> Option<&PathBuf> {
+        self.0.range_tombstones_file_path.as_ref()
+    }
 }
 
 /// Signals whether or not compaction should be performed
@@ -176,71 +496,117 @@ pub(crate) enum ShouldCompact {
 
 #[async_trait]
 pub(crate) trait FileBundleHandle {
-    /// Gets a uncommitted new file bundle on level 0.
+    /// The options new file bundles are written with.
+    fn options(&self) -> &Options;
+
+    /// Gets a uncommitted new file bundle on `level` holding keys in `key_range` with sequence
+    /// numbers up to `largest_seq`, and a file for range tombstones if `has_range_tombstones`.
     /// Uncommitted means it is not yet visible to the outside.
     async fn new_file_bundle(
         &self,
         level: Level,
+        key_range: KeyRange,
+        largest_seq: SequenceNumber,
+        has_range_tombstones: bool,
     ) -> UncommittedFileBundle;
 
     /// Commit and uncommitted file bundle and make it therefore visible to the outside.
     async fn commit_file_bundle(
         &self,
         uncommitted_bundle: UncommittedFileBundle,
-    ) -> ShouldCompact;
+    ) -> Result<ShouldCompact>;
 
-    /// Remove bundles from `level`.
-    /// Returns the number of deleted file bundles.
-    async fn remove_bundles(
-        &mut self,
+    /// Atomically replaces the bundles in `bundles_to_remove` with the uncommitted bundle, if any,
+    /// and deletes the files of the removed bundles afterwards.
+    async fn replace_bundles(
+        &self,
         bundles_to_remove: &HashSet<FileBundleId>,
-    ) -> usize;
+        uncommitted_bundle: Option<UncommittedFileBundle>,
+    ) -> Result<ShouldCompact>;
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct FileBundles(Arc<RwLock<FileBundlesLevelled>>);
+pub(crate) struct FileBundles {
+    levelled: Arc<RwLock<FileBundlesLevelled>>,
+    // Serialises changes to the file set so that they are applied in the order they are recorded.
+    manifest: Arc<Mutex<Manifest>>,
+    options: Options,
+}
 
 impl FileBundles {
-    pub fn new(base_path: PathBuf) -> Self {
-        Self(Arc::new(RwLock::new(FileBundlesLevelled::new(base_path))))
+    /// Recovers the file bundles stored in `base_path` from its manifest.
+    /// New bundles are written and compacted as configured by the options.
+    pub async fn recover(
+        base_path: PathBuf,
+        options: Options,
+    ) -> Result<Self> {
+        let (manifest, levelled) = Manifest::open(&base_path).await?;
+        levelled.remove_unreferenced_files().await?;
+        Ok(Self {
+            levelled: Arc::new(RwLock::new(levelled)),
+            manifest: Arc::new(Mutex::new(manifest)),
+            options,
+        })
     }
 
     pub fn inner(&self) -> Arc<RwLock<FileBundlesLevelled>> {
-        self.0.clone()
+        self.levelled.clone()
+    }
+
+    /// Durably records the version edit in the manifest before making it visible.
+    /// Returns the removed bundles and whether the level of the added bundles should be compacted.
+    async fn apply(
+        &self,
+        edit: VersionEdit,
+    ) -> Result<(Vec<FileBundle>, ShouldCompact)> {
+        let added_level = edit.added.first().map(|bundle| bundle.level);
+        let mut manifest = self.manifest.lock().await;
+        manifest.record(&edit).await?;
+        let mut lock = self.levelled.write().await;
+        let removed_bundles = lock.apply(edit);
+        let should_compact = added_level
+            .map(|level| lock.should_compact(level, &self.options))
+            .unwrap_or(ShouldCompact::No);
+        Ok((removed_bundles, should_compact))
     }
 }
 
 #[async_trait]
 impl FileBundleHandle for FileBundles {
+    fn options(&self) -> &Options {
+        &self.options
+    }
+
     async fn new_file_bundle(
         &self,
         level: Level,
+        key_range: KeyRange,
+        largest_seq: SequenceNumber,
+        has_range_tombstones: bool,
     ) -> UncommittedFileBundle {
-        let read_lock = self.0.read().await;
-        let bundle_length = match level {
-            Level::L0 => read_lock.l0.len(),
-            Level::L1 => read_lock.l1.len(),
-            Level::L2 => read_lock.l2.len(),
-        };
-        let base_path = read_lock.base_path.clone();
-        drop(read_lock);
+        let file_number = self.manifest.lock().await.allocate_file_number();
+        let base_path = self.levelled.read().await.base_path.clone();
 
-        // TODO better naming convention?
-        let main_data_file_name = PathBuf::from(&format!("{:?}-data-{}.db", level, bundle_length));
-        let index_file_name = PathBuf::from(&format!("{:?}-index-{}.db", level, bundle_length));
+        let main_data_file_name = PathBuf::from(&format!("{:?}-data-{}.db", level, file_number));
+        let index_file_name = PathBuf::from(&format!("{:?}-index-{}.db", level, file_number));
         let bloom_filter_file_name =
-            PathBuf::from(&format!("{:?}-bloom-{}.db", level, bundle_length));
+            PathBuf::from(&format!("{:?}-bloom-{}.db", level, file_number));
 
         let main_data_file_path = Path::join(&base_path, main_data_file_name);
         let index_file_path = Path::join(&base_path, index_file_name);
         let bloom_filter_file_path = Path::join(&base_path, bloom_filter_file_name);
+        let range_tombstones_file_path = has_range_tombstones
+            .then(|| Path::join(&base_path, format!("{:?}-range-{}.db", level, file_number)));
 
         let bundle = FileBundle {
             id: FileBundleId::new(),
             main_data_file_path,
             index_file_path,
             bloom_filter_file_path,
+            range_tombstones_file_path,
             level,
+            key_range,
+            largest_seq,
         };
         UncommittedFileBundle(bundle)
     }
@@ -248,78 +614,46 @@ impl FileBundleHandle for FileBundles {
     async fn commit_file_bundle(
         &self,
         uncommitted_bundle: UncommittedFileBundle,
-    ) -> ShouldCompact {
-        let mut lock = self.0.write().await;
-        let should_compact = match uncommitted_bundle.0.level {
-            Level::L0 => {
-                lock.l0.push_front(uncommitted_bundle.into_inner());
-                lock.l0.len() >= 4
-            }
-            Level::L1 => {
-                lock.l1.push_front(uncommitted_bundle.into_inner());
-                lock.l1.len() >= 8
-            }
-            Level::L2 => {
-                lock.l2.push_front(uncommitted_bundle.into_inner());
-                // Never compact the last level
-                false
-            }
+    ) -> Result<ShouldCompact> {
+        let edit = VersionEdit {
+            added: vec![uncommitted_bundle.into_inner()],
+            removed: Default::default(),
         };
-
-        if should_compact {
-            ShouldCompact::Yes
-        } else {
-            ShouldCompact::No
-        }
+        let (_, should_compact) = self.apply(edit).await?;
+        Ok(should_compact)
     }
 
-    async fn remove_bundles(
-        &mut self,
+    async fn replace_bundles(
+        &self,
         bundles_to_remove: &HashSet<FileBundleId>,
-    ) -> usize {
-        let mut files_to_delete = Vec::with_capacity(bundles_to_remove.len());
-        let mut lock = self.0.write().await;
-        // TODO: DRY
-        let mut i = 0;
-        while i < lock.l0.len() {
-            if bundles_to_remove.contains(&lock.l0[i].id) {
-                files_to_delete.push(lock.l0.remove(i).unwrap())
-            } else {
-                i += 1;
-            }
-        }
-        let mut i = 0;
-        while i < lock.l1.len() {
-            if bundles_to_remove.contains(&lock.l1[i].id) {
-                files_to_delete.push(lock.l1.remove(i).unwrap())
-            } else {
-                i += 1;
-            }
-        }
-        let mut i = 0;
-        while i < lock.l2.len() {
-            if bundles_to_remove.contains(&lock.l2[i].id) {
-                files_to_delete.push(lock.l2.remove(i).unwrap())
-            } else {
-                i += 1;
-            }
-        }
-        drop(lock);
+        uncommitted_bundle: Option<UncommittedFileBundle>,
+    ) -> Result<ShouldCompact> {
+        let edit = VersionEdit {
+            added: uncommitted_bundle
+                .map(UncommittedFileBundle::into_inner)
+                .into_iter()
+                .collect(),
+            removed: bundles_to_remove.clone(),
+        };
+        let (removed_bundles, should_compact) = self.apply(edit).await?;
 
-        let n_deleted_files = files_to_delete.len();
+        // The removed bundles are not visible anymore, so their files can be deleted safely.
         for FileBundle {
-            id: _,
             main_data_file_path,
             index_file_path,
             bloom_filter_file_path,
-            level: _,
-        } in files_to_delete
+            range_tombstones_file_path,
+            ..
+        } in removed_bundles
         {
-            remove_file(bloom_filter_file_path).await.unwrap();
-            remove_file(index_file_path).await.unwrap();
-            remove_file(main_data_file_path).await.unwrap();
+            if let Some(range_tombstones_file_path) = range_tombstones_file_path {
+                remove_file(range_tombstones_file_path).await?;
+            }
+            remove_file(bloom_filter_file_path).await?;
+            remove_file(index_file_path).await?;
+            remove_file(main_data_file_path).await?;
         }
-        n_deleted_files
+        Ok(should_compact)
     }
 }
 
@@ -329,23 +663,26 @@ mod tests {
 
     use super::*;
 
-    #[test]
-    fn test_bundle_iterator_works_correctly() {
-        impl FileBundle {
-            fn new_with_path_level(
-                main_path: &Path,
-                level: Level,
-            ) -> Self {
-                Self {
-                    id: FileBundleId::new(),
-                    main_data_file_path: main_path.to_path_buf(),
-                    index_file_path: Default::default(),
-                    bloom_filter_file_path: Default::default(),
-                    level,
-                }
+    impl FileBundle {
+        fn new_with_path_level(
+            main_path: &Path,
+            level: Level,
+        ) -> Self {
+            Self {
+                id: FileBundleId::new(),
+                main_data_file_path: main_path.to_path_buf(),
+                index_file_path: Default::default(),
+                bloom_filter_file_path: Default::default(),
+                range_tombstones_file_path: None,
+                level,
+                key_range: Default::default(),
+                largest_seq: 0,
             }
         }
+    }
 
+    #[test]
+    fn test_bundle_iterator_works_correctly() {
         let mut l0 = VecDeque::new();
         let mut l1 = VecDeque::new();
         let mut l2 = VecDeque::new();
@@ -386,4 +723,106 @@ mod tests {
             assert_eq!(bundle.main_data_file_path, path);
         }
     }
+
+    #[test]
+    fn test_parse_bundle_file_name() {
+        assert!(matches!(
+            parse_bundle_file_name("L1-index-12.db"),
+            Some((Level::L1, BundleFileKind::Index, 12))
+        ));
+        assert!(matches!(
+            parse_bundle_file_name("L2-bloom-0.db"),
+            Some((Level::L2, BundleFileKind::Bloom, 0))
+        ));
+        assert!(matches!(
+            parse_bundle_file_name("L0-range-3.db"),
+            Some((Level::L0, BundleFileKind::RangeTombstones, 3))
+        ));
+        assert!(parse_bundle_file_name("L3-data-0.db").is_none());
+        assert!(parse_bundle_file_name("L0-data-0.log").is_none());
+        assert!(parse_bundle_file_name("wal-0.log").is_none());
+    }
+
+    async fn write_empty_files(bundle: &UncommittedFileBundle) {
+        for path in [
+            bundle.main_data_file_path(),
+            bundle.index_file_path(),
+            bundle.bloom_filter_file_path(),
+        ]
+        .into_iter()
+        .chain(bundle.range_tombstones_file_path())
+        {
+            tokio::fs::write(path, []).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_bundles_are_recovered_from_manifest() {
+        let path = PathBuf::from(format!("./test-logs/{:?}", Uuid::new_v4()));
+        tokio::fs::create_dir_all(&path).await.unwrap();
+        let key_range = KeyRange {
+            smallest: Bytes::from("a"),
+            largest: Bytes::from("z"),
+        };
+
+        let bundles = FileBundles::recover(path.clone(), Options::default())
+            .await
+            .unwrap();
+        let first = bundles
+            .new_file_bundle(Level::L0, key_range.clone(), 1, false)
+            .await;
+        let first_id = first.0.id;
+        write_empty_files(&first).await;
+        bundles.commit_file_bundle(first).await.unwrap();
+        let second = bundles
+            .new_file_bundle(Level::L0, key_range.clone(), 1, false)
+            .await;
+        let second_id = second.0.id;
+        write_empty_files(&second).await;
+        bundles.commit_file_bundle(second).await.unwrap();
+        let compacted = bundles
+            .new_file_bundle(Level::L1, key_range.clone(), 2, true)
+            .await;
+        let compacted_id = compacted.0.id;
+        write_empty_files(&compacted).await;
+        bundles
+            .replace_bundles(&HashSet::from([first_id]), Some(compacted))
+            .await
+            .unwrap();
+        // A file of a flush that was interrupted before it was committed
+        let orphaned_file_path = path.join("L0-data-9.db");
+        tokio::fs::write(&orphaned_file_path, []).await.unwrap();
+        drop(bundles);
+
+        let recovered = FileBundles::recover(path.clone(), Options::default())
+            .await
+            .unwrap();
+        let lock = recovered.levelled.read().await;
+        assert_eq!(
+            lock.l0.iter().map(|bundle| bundle.id).collect_vec(),
+            vec![second_id]
+        );
+        assert_eq!(
+            lock.l1.iter().map(|bundle| bundle.id).collect_vec(),
+            vec![compacted_id]
+        );
+        assert_eq!(lock.l1[0].key_range, key_range);
+        assert_eq!(
+            lock.l1[0].range_tombstones_file_path,
+            Some(path.join("L1-range-2.db"))
+        );
+        assert_eq!(lock.l0[0].range_tombstones_file_path, None);
+        assert!(lock.l2.is_empty());
+        assert_eq!(lock.largest_seq(), Some(2));
+        assert!(!orphaned_file_path.exists());
+        drop(lock);
+
+        // File numbers keep increasing across restarts, even after bundles were removed
+        let next = recovered
+            .new_file_bundle(Level::L0, key_range, 3, false)
+            .await;
+        assert_eq!(next.main_data_file_path(), &path.join("L0-data-3.db"));
+
+        let _ = tokio::fs::remove_dir_all(path).await;
+    }
 }
@@ -1,12 +1,15 @@
 use std::fmt::Debug;
+use std::path::Path;
 
-use anyhow::Result;
 use tokio::fs::OpenOptions;
 use tokio::io::AsyncWriteExt;
 
+use crate::checksum::append_checksum;
+use crate::error::Result;
 use crate::file_handling::file_bundle::FileBundleHandle;
 use crate::file_handling::file_bundle::Level;
 use crate::file_handling::file_bundle::ShouldCompact;
+use crate::file_handling::file_bundle::UncommittedFileBundle;
 use crate::serialization::Serialize;
 use crate::serialization::SerializedTableData;
 
@@ -15,6 +18,23 @@ pub(super) async fn flush<S, B>(
     handler: B,
     level: Level,
 ) -> Result<ShouldCompact>
+where
+    S: Serialize,
+    S: Send,
+    S: Debug,
+    B: FileBundleHandle,
+{
+    let uncommited_bundle = write_file_bundle(data, &handler, level).await?;
+    // We can only commit and thus make visible the files after they were successfully written
+    handler.commit_file_bundle(uncommited_bundle).await
+}
+
+/// Writes the data to a new file bundle on `level` without making it visible yet.
+pub(super) async fn write_file_bundle<S, B>(
+    data: S,
+    handler: &B,
+    level: Level,
+) -> Result<UncommittedFileBundle>
 where
     S: Serialize,
     S: Send,
@@ -25,29 +45,41 @@ where
         main_data,
         offsets,
         bloom_filter,
-    } = data.serialize()?;
-    let uncommited_bundle = handler.new_file_bundle(level).await;
-    let mut main_data_file = OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(uncommited_bundle.main_data_file_path())
-        .await?;
-    main_data_file.write_all(&main_data).await?;
-    let mut index_file = OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(uncommited_bundle.index_file_path())
-        .await?;
-    index_file.write_all(&offsets).await?;
-    let mut bloom_filter_file = OpenOptions::new()
+        key_range,
+        largest_seq,
+        range_tombstones,
+    } = data.serialize(handler.options())?;
+    let uncommited_bundle = handler
+        .new_file_bundle(level, key_range, largest_seq, range_tombstones.is_some())
+        .await;
+    // The data blocks are checksummed individually, the index and bloom filter as a whole.
+    write_new_file(uncommited_bundle.main_data_file_path(), &main_data).await?;
+    let mut index_bytes = offsets;
+    append_checksum(&mut index_bytes);
+    write_new_file(uncommited_bundle.index_file_path(), &index_bytes).await?;
+    let mut bloom_bytes: Vec<u8> = bloom_filter.into();
+    append_checksum(&mut bloom_bytes);
+    write_new_file(uncommited_bundle.bloom_filter_file_path(), &bloom_bytes).await?;
+    if let (Some(path), Some(range_tombstones)) = (
+        uncommited_bundle.range_tombstones_file_path(),
+        range_tombstones,
+    ) {
+        write_new_file(path, &range_tombstones).await?;
+    }
+    Ok(uncommited_bundle)
+}
+
+async fn write_new_file(
+    path: &Path,
+    bytes: &[u8],
+) -> Result<()> {
+    let mut file = OpenOptions::new()
         .write(true)
         .create_new(true)
-        .open(uncommited_bundle.bloom_filter_file_path())
+        .open(path)
         .await?;
-    let bloom_bytes: Vec<u8> = bloom_filter.into();
-    bloom_filter_file.write_all(&bloom_bytes).await?;
-
-    // We can only commit and thus make visible the files after they were successfully written
-    let should_compact = handler.commit_file_bundle(uncommited_bundle).await;
-    Ok(should_compact)
+    file.write_all(bytes).await?;
+    // The file must be durable before the manifest can reference it
+    file.sync_all().await?;
+    Ok(())
 }
@@ -0,0 +1,217 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use byteorder::BigEndian;
+use byteorder::ReadBytesExt;
+use tokio::fs::rename;
+use tokio::fs::File;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+
+use crate::deserialization::read_records;
+use crate::error::Result;
+use crate::file_handling::file_bundle::FileBundle;
+use crate::file_handling::file_bundle::FileBundleId;
+use crate::file_handling::file_bundle::FileBundlesLevelled;
+use crate::file_handling::sync_dir;
+use crate::serialization::write_record;
+
+const MANIFEST_FILE_NAME: &str = "MANIFEST";
+const MANIFEST_TMP_FILE_NAME: &str = "MANIFEST.tmp";
+
+/// An atomic change to the set of live file bundles.
+#[derive(Debug, Default)]
+pub(crate) struct VersionEdit {
+    pub added: Vec<FileBundle>,
+    pub removed: HashSet<FileBundleId>,
+}
+
+impl VersionEdit {
+    fn encode(&self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        buffer.extend((self.added.len() as u64).to_be_bytes());
+        for bundle in self.added.iter() {
+            bundle.encode(&mut buffer)?;
+        }
+        buffer.extend((self.removed.len() as u64).to_be_bytes());
+        for id in self.removed.iter() {
+            id.encode(&mut buffer);
+        }
+        Ok(buffer)
+    }
+
+    fn decode(
+        bytes: &mut &[u8],
+        base_path: &Path,
+    ) -> Result<Self> {
+        let n_added = ReadBytesExt::read_u64::<BigEndian>(bytes)? as usize;
+        let added = (0..n_added)
+            .map(|_| FileBundle::decode(bytes, base_path))
+            .collect::<Result<_>>()?;
+        let n_removed = ReadBytesExt::read_u64::<BigEndian>(bytes)? as usize;
+        let removed = (0..n_removed)
+            .map(|_| FileBundleId::decode(bytes))
+            .collect::<Result<_>>()?;
+        Ok(Self { added, removed })
+    }
+}
+
+/// The manifest is an append-only log of version edits describing which file bundles are live.
+///
+/// Every change to the file set is recorded here before it becomes visible, so the file set can
+/// be rebuilt from the manifest alone after a restart or crash.
+/// Each record also holds the next file number to hand out, so that file names are never reused.
+#[derive(Debug)]
+pub(crate) struct Manifest {
+    file: File,
+    next_file_number: u64,
+    // The length of the manifest up to the end of its last complete record
+    len: u64,
+    // Whether a failed append may have left a torn record after `len`
+    is_torn: bool,
+}
+
+impl Manifest {
+    /// Opens the manifest in `base_path` and rebuilds the live file bundles from it.
+    /// A fresh manifest is created if none exists yet.
+    pub(crate) async fn open(base_path: &Path) -> Result<(Self, FileBundlesLevelled)> {
+        let path = base_path.join(MANIFEST_FILE_NAME);
+        let mut levelled = FileBundlesLevelled::new(base_path.to_path_buf());
+        let mut next_file_number = 0;
+        if path.exists() {
+            let mut manifest_bytes = Vec::new();
+            File::open(&path)
+                .await?
+                .read_to_end(&mut manifest_bytes)
+                .await?;
+            // A torn record at the end is dropped along with the rest of the old manifest below
+            for record in read_records(&manifest_bytes, &path) {
+                let mut record = record?;
+                let recorded_next_file_number = ReadBytesExt::read_u64::<BigEndian>(&mut record)?;
+                next_file_number = next_file_number.max(recorded_next_file_number);
+                levelled.apply(VersionEdit::decode(&mut record, base_path)?);
+            }
+        }
+
+        // Compact the manifest into a single edit so that it does not grow indefinitely.
+        // The new manifest is only swapped in once it is fully written.
+        let tmp_path = base_path.join(MANIFEST_TMP_FILE_NAME);
+        let mut snapshot = Vec::new();
+        write_record(
+            &mut snapshot,
+            &encode_record(next_file_number, &levelled.snapshot())?,
+        );
+        let mut tmp_file = File::create(&tmp_path).await?;
+        tmp_file.write_all(&snapshot).await?;
+        tmp_file.sync_all().await?;
+        drop(tmp_file);
+        rename(&tmp_path, &path).await?;
+        sync_dir(base_path).await?;
+
+        let file = OpenOptions::new().append(true).open(&path).await?;
+        Ok((
+            Self {
+                file,
+                next_file_number,
+                len: snapshot.len() as u64,
+                is_torn: false,
+            },
+            levelled,
+        ))
+    }
+
+    /// Hands out a file number that has not been used before.
+    /// It is persisted with the next recorded edit, which is at the latest the one making the
+    /// file visible. Files of numbers that were handed out but never recorded are unreferenced
+    /// and hence removed on recovery, so their numbers can be safely reused.
+    pub(crate) fn allocate_file_number(&mut self) -> u64 {
+        let file_number = self.next_file_number;
+        self.next_file_number += 1;
+        file_number
+    }
+
+    /// Durably records the version edit.
+    pub(crate) async fn record(
+        &mut self,
+        edit: &VersionEdit,
+    ) -> Result<()> {
+        let mut record = Vec::new();
+        write_record(&mut record, &encode_record(self.next_file_number, edit)?);
+        self.truncate_torn_record().await?;
+        let result = self.append(&record).await;
+        if result.is_err() {
+            // Should this fail as well, it is retried before the next edit is recorded
+            let _ = self.truncate_torn_record().await;
+        }
+        result
+    }
+
+    async fn append(
+        &mut self,
+        record: &[u8],
+    ) -> Result<()> {
+        // Set until the record is on disk, which it may partially be if writing it fails
+        self.is_torn = true;
+        self.file.write_all(record).await?;
+        self.file.flush().await?;
+        self.file.sync_data().await?;
+        self.is_torn = false;
+        self.len += record.len() as u64;
+        Ok(())
+    }
+
+    /// Cuts off what a failed append left behind after the last complete record.
+    /// Reading the manifest stops at a torn record, so the bundles of later edits would be
+    /// removed as unreferenced on recovery otherwise.
+    async fn truncate_torn_record(&mut self) -> Result<()> {
+        if self.is_torn {
+            self.file.set_len(self.len).await?;
+            self.file.sync_data().await?;
+            self.is_torn = false;
+        }
+        Ok(())
+    }
+}
+
+fn encode_record(
+    next_file_number: u64,
+    edit: &VersionEdit,
+) -> Result<Vec<u8>> {
+    let mut record = next_file_number.to_be_bytes().to_vec();
+    record.extend(edit.encode()?);
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tokio::fs::create_dir_all;
+    use tokio::fs::remove_dir_all;
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn edits_after_a_failed_append_are_recovered() {
+        let path = PathBuf::from(format!("./test-logs/{:?}", Uuid::new_v4()));
+        create_dir_all(&path).await.unwrap();
+
+        let (mut manifest, _) = Manifest::open(&path).await.unwrap();
+        manifest.allocate_file_number();
+        manifest.record(&VersionEdit::default()).await.unwrap();
+        // An append that failed after part of its record reached the file
+        manifest.is_torn = true;
+        manifest.file.write_all(&[0xff; 5]).await.unwrap();
+        manifest.file.flush().await.unwrap();
+        manifest.allocate_file_number();
+        manifest.record(&VersionEdit::default()).await.unwrap();
+        drop(manifest);
+
+        let (manifest, _) = Manifest::open(&path).await.unwrap();
+        assert_eq!(manifest.next_file_number, 2);
+
+        let _ = remove_dir_all(path).await;
+    }
+}
@@ -1,13 +1,19 @@
 use std::fmt::Debug;
+use std::mem;
+use std::panic;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
 
-use anyhow::Result;
 use async_trait::async_trait;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
 
-use crate::file_handling::file_bundle::FileBundles;
+use crate::error::BackgroundErrorState;
+use crate::error::Error;
+use crate::error::Result;
 use crate::file_handling::file_bundle::Level;
 use crate::file_handling::file_bundle::ShouldCompact;
 use crate::file_handling::flushing::flush;
@@ -15,20 +21,25 @@ use crate::file_handling::flushing::flush;
 mod compaction;
 mod file_bundle;
 mod flushing;
+mod manifest;
 
+pub(crate) use file_bundle::FileBundles;
+pub(crate) use file_bundle::KeyRange;
 pub(crate) use file_bundle::SstFileBundle;
 
 use crate::file_handling::compaction::Compaction;
 use crate::memtable::MemTable;
+use crate::options::Options;
+use crate::snapshot::SnapshotList;
 
 #[async_trait]
 pub(crate) trait FileHandling {
+    /// Flushes the data to a new L0 bundle of the column family owning `file_bundles`.
     async fn flush(
-        &mut self,
+        &self,
+        file_bundles: &FileBundles,
         data: MemTable,
     ) -> Result<()>;
-
-    fn file_bundles(&self) -> &FileBundles;
 }
 
 #[async_trait]
@@ -41,49 +52,70 @@ pub(crate) trait DataHandling {
         P: Send;
 }
 
+/// Durably records the files created, renamed or removed in the directory at `path`, which
+/// syncing the files themselves does not.
+pub(crate) async fn sync_dir(path: &Path) -> Result<()> {
+    // Directories cannot be opened like files on Windows, which persists such changes with the
+    // file itself
+    #[cfg(unix)]
+    tokio::fs::File::open(path).await?.sync_all().await?;
+    Ok(())
+}
+
 #[derive(Debug)]
 struct FlushData {
     data: MemTable,
+    file_bundles: FileBundles,
     response_channel: oneshot::Sender<Result<()>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct SstFileHandler {
-    file_bundles: FileBundles,
-    flush_sender: mpsc::Sender<FlushData>,
+    // `None` once closed, the only sender is shared by all clones so that closing stops the tasks
+    flush_sender: Arc<Mutex<Option<mpsc::Sender<FlushData>>>>,
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
 }
 
 impl SstFileHandler {
-    pub(crate) fn new<P>(path: P) -> Self
-    where
-        P: AsRef<Path>,
-        P: Into<PathBuf>,
-    {
-        let file_bundles = FileBundles::new(path.into());
-        let file_bundles_clone_1 = file_bundles.clone();
-        let file_bundles_clone_2 = file_bundles.clone();
-        let (flush_tx, mut flush_rx) = mpsc::channel::<FlushData>(1);
-        // TODO investigate impact of buffer size here
-        let (compaction_tx, mut compaction_rx) = mpsc::channel::<()>(1);
-
-        tokio::spawn(async move {
-            while compaction_rx.recv().await.is_some() {
-                // TODO handle errors
-                let _ = file_bundles_clone_1.compact().await;
+    /// Spawns the background tasks that flush and compact the SST bundles of all column families.
+    /// Compaction retains the versions still visible to the live `snapshots` and applies merge
+    /// operands using the merge operator of the `options`. Its failures are recorded in `background_error`, while
+    /// those of flushes are returned to the caller.
+    pub(crate) fn new(
+        snapshots: SnapshotList,
+        options: &Options,
+        background_error: BackgroundErrorState,
+    ) -> Self {
+        let merge_operator = options.merge_operator.clone();
+        let (flush_tx, mut flush_rx) = mpsc::channel::<FlushData>(options.flush_queue_depth);
+        let (compaction_tx, mut compaction_rx) =
+            mpsc::channel::<FileBundles>(options.compaction_queue_depth);
+
+        let compaction_task = tokio::spawn(async move {
+            while let Some(file_bundles) = compaction_rx.recv().await {
+                // The bundles are left as they were, so compaction is simply retried after the next
+                // flush
+                if let Err(error) = file_bundles
+                    .compact(&snapshots, merge_operator.as_deref())
+                    .await
+                {
+                    background_error.record(error);
+                }
             }
         });
 
-        tokio::spawn(async move {
+        let flush_task = tokio::spawn(async move {
             while let Some(flush_data) = flush_rx.recv().await {
                 let FlushData {
                     data,
+                    file_bundles,
                     response_channel: tx,
                 } = flush_data;
-                let flush_result = flush(data, file_bundles_clone_2.clone(), Level::L0).await;
+                let flush_result = flush(data, file_bundles.clone(), Level::L0).await;
                 let result = match flush_result {
                     Ok(should_compact) => {
                         if should_compact == ShouldCompact::Yes {
-                            let _ = compaction_tx.send(()).await;
+                            let _ = compaction_tx.send(file_bundles).await;
                         }
                         Ok(())
                     }
@@ -94,8 +126,25 @@ impl SstFileHandler {
         });
 
         Self {
-            file_bundles,
-            flush_sender: flush_tx,
+            flush_sender: Arc::new(Mutex::new(Some(flush_tx))),
+            tasks: Arc::new(Mutex::new(vec![flush_task, compaction_task])),
+        }
+    }
+
+    /// Stops the background tasks once they have finished the pending flushes and compactions, so
+    /// that the SST bundles are left in a consistent state.
+    /// Later flushes fail with [`Error::Closed`].
+    pub(crate) async fn close(&self) {
+        // Unwrap is OK as the lock is never held while panicking.
+        // Dropping the sender ends the flush task, which in turn ends the compaction task.
+        self.flush_sender.lock().unwrap().take();
+        let tasks = mem::take(&mut *self.tasks.lock().unwrap());
+        for task in tasks {
+            if let Err(error) = task.await {
+                if error.is_panic() {
+                    panic::resume_unwind(error.into_panic());
+                }
+            }
         }
     }
 }
@@ -103,20 +152,23 @@ impl SstFileHandler {
 #[async_trait]
 impl FileHandling for SstFileHandler {
     async fn flush(
-        &mut self,
+        &self,
+        file_bundles: &FileBundles,
         data: MemTable,
     ) -> Result<()> {
         let (tx, rx) = oneshot::channel::<Result<()>>();
         let flush_data = FlushData {
             data,
+            file_bundles: file_bundles.clone(),
             response_channel: tx,
         };
-        self.flush_sender.send(flush_data).await?;
-        let _ = rx.await?;
-        Ok(())
-    }
-
-    fn file_bundles(&self) -> &FileBundles {
-        &self.file_bundles
+        // The flush task only stops once closed or once the runtime shuts down
+        let flush_sender = self.flush_sender.lock().unwrap().clone();
+        flush_sender
+            .ok_or(Error::Closed)?
+            .send(flush_data)
+            .await
+            .map_err(|_| Error::Closed)?;
+        rx.await.map_err(|_| Error::Closed)?
     }
 }
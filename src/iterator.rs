@@ -0,0 +1,771 @@
+use std::fmt;
+use std::future::poll_fn;
+use std::future::Future;
+use std::ops::Bound;
+use std::ops::RangeBounds;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_core::Stream;
+use itertools::Itertools;
+
+use crate::error::Error;
+use crate::error::Result;
+use crate::memtable::current_timestamp;
+use crate::memtable::extend_with_older_versions;
+use crate::memtable::is_complete;
+use crate::memtable::versions_to_read;
+use crate::memtable::SequenceNumber;
+use crate::memtable::Timestamp;
+use crate::memtable::Versions;
+use crate::merge_operator::resolve_versions;
+use crate::merge_operator::MergeOperator;
+use crate::range_tombstone::apply_range_tombstones;
+use crate::range_tombstone::RangeTombstone;
+
+/// The bounds of a key range.
+pub(crate) type KeyBounds = (Bound<Bytes>, Bound<Bytes>);
+
+/// A cursor over the entries of a single memtable or SST bundle in a key range, sitting in a
+/// [`Gap`] between two keys. Each entry holds all versions of its key, newest first, including
+/// tombstones.
+///
+/// The entries are only read when the cursor reaches them, so that a scan never holds more than
+/// the entries it is about to return.
+#[async_trait]
+pub(crate) trait SortedRun: fmt::Debug + Send {
+    /// Reads the entry right after the cursor without moving it.
+    async fn read_next(&mut self) -> Result<Option<(Bytes, Versions)>>;
+
+    /// Reads the entry right before the cursor without moving it.
+    async fn read_prev(&mut self) -> Result<Option<(Bytes, Versions)>>;
+
+    /// Moves the cursor into the gap.
+    fn seek(
+        &mut self,
+        gap: Gap,
+    );
+}
+
+/// A position between two keys of a key range.
+#[derive(Debug, Clone)]
+pub(crate) enum Gap {
+    /// Before the first key of the range.
+    Start,
+    /// Right before the key, so the following keys are the ones not smaller than it.
+    Before(Bytes),
+    /// Right after the key, so the following keys are the ones greater than it.
+    After(Bytes),
+    /// After the last key of the range.
+    End,
+}
+
+impl Gap {
+    /// The bounds of the keys of the range that follow the gap, `None` if there are none.
+    pub(crate) fn bounds_after(
+        &self,
+        range: &KeyBounds,
+    ) -> Option<KeyBounds> {
+        let start = match self {
+            Gap::Start => return Some(range.clone()),
+            Gap::Before(key) => Bound::Included(key.clone()),
+            Gap::After(key) => Bound::Excluded(key.clone()),
+            Gap::End => return None,
+        };
+        let start = match (&start, &range.0) {
+            (Bound::Included(key) | Bound::Excluded(key), Bound::Included(range_start))
+                if key < range_start =>
+            {
+                range.0.clone()
+            }
+            (Bound::Included(key), Bound::Excluded(range_start)) if key <= range_start => {
+                range.0.clone()
+            }
+            (Bound::Excluded(key), Bound::Excluded(range_start)) if key < range_start => {
+                range.0.clone()
+            }
+            _ => start,
+        };
+        let bounds = (start, range.1.clone());
+        (!is_empty_range(&bounds)).then_some(bounds)
+    }
+
+    /// The bounds of the keys of the range that precede the gap, `None` if there are none.
+    pub(crate) fn bounds_before(
+        &self,
+        range: &KeyBounds,
+    ) -> Option<KeyBounds> {
+        let end = match self {
+            Gap::Start => return None,
+            Gap::Before(key) => Bound::Excluded(key.clone()),
+            Gap::After(key) => Bound::Included(key.clone()),
+            Gap::End => return Some(range.clone()),
+        };
+        let end = match (&end, &range.1) {
+            (Bound::Included(key) | Bound::Excluded(key), Bound::Included(range_end))
+                if key > range_end =>
+            {
+                range.1.clone()
+            }
+            (Bound::Included(key), Bound::Excluded(range_end)) if key >= range_end => {
+                range.1.clone()
+            }
+            (Bound::Excluded(key), Bound::Excluded(range_end)) if key > range_end => {
+                range.1.clone()
+            }
+            _ => end,
+        };
+        let bounds = (range.0.clone(), end);
+        (!is_empty_range(&bounds)).then_some(bounds)
+    }
+}
+
+/// Converts any range over byte-like keys into owned key bounds.
+pub(crate) fn to_key_bounds<K, R>(range: R) -> KeyBounds
+where
+    K: AsRef<[u8]>,
+    R: RangeBounds<K>,
+{
+    (
+        range
+            .start_bound()
+            .map(|key| Bytes::copy_from_slice(key.as_ref())),
+        range
+            .end_bound()
+            .map(|key| Bytes::copy_from_slice(key.as_ref())),
+    )
+}
+
+/// Whether no key can fall into the bounds.
+pub(crate) fn is_empty_range(range: &KeyBounds) -> bool {
+    match range {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end))
+        | (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+        _ => false,
+    }
+}
+
+/// Whether the key lies in front of the start of the range.
+pub(crate) fn is_before_start(
+    key: &[u8],
+    range: &KeyBounds,
+) -> bool {
+    match range.start_bound() {
+        Bound::Included(start) => key < start.as_ref(),
+        Bound::Excluded(start) => key <= start.as_ref(),
+        Bound::Unbounded => false,
+    }
+}
+
+/// Whether the key lies behind the end of the range.
+pub(crate) fn is_past_end(
+    key: &[u8],
+    range: &KeyBounds,
+) -> bool {
+    match range.end_bound() {
+        Bound::Included(end) => key > end.as_ref(),
+        Bound::Excluded(end) => key >= end.as_ref(),
+        Bound::Unbounded => false,
+    }
+}
+
+/// A sorted run together with the entries next to its cursor, once they were read.
+#[derive(Debug)]
+struct Source {
+    run: Box<dyn SortedRun>,
+    next: Option<Option<(Bytes, Versions)>>,
+    prev: Option<Option<(Bytes, Versions)>>,
+}
+
+impl Source {
+    async fn peek_next_key(&mut self) -> Result<Option<Bytes>> {
+        if self.next.is_none() {
+            self.next = Some(self.run.read_next().await?);
+        }
+        Ok(self
+            .next
+            .iter()
+            .flatten()
+            .map(|(key, _)| key.clone())
+            .next())
+    }
+
+    async fn peek_prev_key(&mut self) -> Result<Option<Bytes>> {
+        if self.prev.is_none() {
+            self.prev = Some(self.run.read_prev().await?);
+        }
+        Ok(self
+            .prev
+            .iter()
+            .flatten()
+            .map(|(key, _)| key.clone())
+            .next())
+    }
+
+    /// Returns the versions of the key after the cursor, which must have been peeked, and moves
+    /// the cursor past it.
+    fn advance(&mut self) -> Versions {
+        // Unwrap is OK as the key was peeked before
+        let (key, versions) = self.next.take().flatten().unwrap();
+        self.seek(Gap::After(key));
+        versions
+    }
+
+    /// Like [`Source::advance`], but moving the cursor backwards.
+    fn retreat(&mut self) -> Versions {
+        let (key, versions) = self.prev.take().flatten().unwrap();
+        self.seek(Gap::Before(key));
+        versions
+    }
+
+    fn seek(
+        &mut self,
+        gap: Gap,
+    ) {
+        self.next = None;
+        self.prev = None;
+        self.run.seek(gap);
+    }
+}
+
+/// The sources of a [`DbIterator`] and what is needed to merge their versions.
+#[derive(Debug)]
+struct MergedSources {
+    // Ordered from newest to oldest
+    sources: Vec<Source>,
+    range_tombstones: Vec<RangeTombstone>,
+    snapshot: SequenceNumber,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    // Expiry is checked against the time the iterator was created at
+    now: Timestamp,
+}
+
+/// The entry after the cursor being read while the iterator is polled as a stream. The future
+/// owns the sources until it is done, so that it can outlive a cancelled poll.
+type NextEntry = Pin<Box<dyn Future<Output = (Box<MergedSources>, Result<Option<Entry>>)> + Send>>;
+
+/// A key together with its value.
+type Entry = (Bytes, Bytes);
+
+impl MergedSources {
+    /// Reads the value of the key from its versions across all sources, newest first.
+    fn resolve(
+        &self,
+        key: &[u8],
+        versions: Versions,
+    ) -> Result<Option<Bytes>> {
+        let versions = apply_range_tombstones(versions, &self.range_tombstones, key, self.snapshot);
+        resolve_versions(key, versions, self.merge_operator.as_deref(), self.now)
+    }
+
+    /// Moves all sources into the gap.
+    fn seek(
+        &mut self,
+        gap: Gap,
+    ) {
+        for source in self.sources.iter_mut() {
+            source.seek(gap.clone());
+        }
+    }
+
+    /// Like [`MergedSources::next`], but owning the sources, see [`NextEntry`].
+    async fn into_next(mut self: Box<Self>) -> (Box<Self>, Result<Option<Entry>>) {
+        let entry = self.next().await;
+        (self, entry)
+    }
+
+    async fn next(&mut self) -> Result<Option<Entry>> {
+        loop {
+            let mut smallest_key: Option<Bytes> = None;
+            for source in self.sources.iter_mut() {
+                if let Some(key) = source.peek_next_key().await? {
+                    if smallest_key.as_ref().is_none_or(|smallest| key < *smallest) {
+                        smallest_key = Some(key);
+                    }
+                }
+            }
+            let Some(smallest_key) = smallest_key else {
+                return Ok(None);
+            };
+            // Advance every source past the key, keeping the versions needed to read it
+            let mut versions = Versions::new();
+            for source in self.sources.iter_mut() {
+                if source.peek_next_key().await?.as_ref() == Some(&smallest_key) {
+                    let source_versions = source.advance();
+                    if !is_complete(&versions) {
+                        extend_with_older_versions(
+                            &mut versions,
+                            versions_to_read(&source_versions, self.snapshot),
+                        );
+                    }
+                }
+            }
+            if let Some(value) = self.resolve(&smallest_key, versions)? {
+                return Ok(Some((smallest_key, value)));
+            }
+        }
+    }
+
+    async fn prev(&mut self) -> Result<Option<Entry>> {
+        loop {
+            let mut largest_key: Option<Bytes> = None;
+            for source in self.sources.iter_mut() {
+                if let Some(key) = source.peek_prev_key().await? {
+                    if largest_key.as_ref().is_none_or(|largest| key > *largest) {
+                        largest_key = Some(key);
+                    }
+                }
+            }
+            let Some(largest_key) = largest_key else {
+                return Ok(None);
+            };
+            // Move every source back past the key, keeping the versions needed to read it
+            let mut versions = Versions::new();
+            for source in self.sources.iter_mut() {
+                if source.peek_prev_key().await?.as_ref() == Some(&largest_key) {
+                    let source_versions = source.retreat();
+                    if !is_complete(&versions) {
+                        extend_with_older_versions(
+                            &mut versions,
+                            versions_to_read(&source_versions, self.snapshot),
+                        );
+                    }
+                }
+            }
+            if let Some(value) = self.resolve(&largest_key, versions)? {
+                return Ok(Some((largest_key, value)));
+            }
+        }
+    }
+}
+
+/// An ordered iterator over the key-value pairs of a key range, polled as a [`Stream`].
+///
+/// It merges the sorted runs of the memtables and all SST bundles. If a key is stored in more than
+/// one of them, only the newest value is returned, and keys whose newest value is a tombstone are
+/// skipped altogether, as are keys deleted by a range tombstone. Merge operands are applied to the
+/// older value of their key.
+/// The iterator reads a consistent snapshot of the range, so writes made after it was created are
+/// not visible to it.
+///
+/// The data blocks of the SST bundles are only read once the iterator reaches them. Should reading
+/// one fail, the iterator ends early and [`DbIterator::error`] returns the cause.
+///
+/// The iterator acts as a cursor sitting between two keys, starting before the first one.
+/// [`DbIterator::next`] returns the key after the cursor and [`DbIterator::prev`] the one before
+/// it, moving the cursor past that key. Calling `prev` right after `next` hence returns the same
+/// entry again. A cancelled `next` still moves the cursor once it is resumed or `prev` is called.
+pub struct DbIterator {
+    // Taken by the entry being read while the iterator is polled as a stream
+    sources: Option<Box<MergedSources>>,
+    next_entry: Option<NextEntry>,
+    // Where the cursor moves to once the entry being read is done
+    pending_seek: Option<Gap>,
+    error: Option<Error>,
+}
+
+impl fmt::Debug for DbIterator {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        f.debug_struct("DbIterator")
+            .field("sources", &self.sources)
+            .field("is_reading_next_entry", &self.next_entry.is_some())
+            .field("pending_seek", &self.pending_seek)
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+impl DbIterator {
+    /// Creates an iterator merging the sorted runs as of the snapshot, applying the range
+    /// tombstones of all of them.
+    /// The runs must be ordered from newest to oldest.
+    pub(crate) fn new(
+        runs: Vec<Box<dyn SortedRun>>,
+        range_tombstones: Vec<RangeTombstone>,
+        snapshot: SequenceNumber,
+        merge_operator: Option<Arc<dyn MergeOperator>>,
+    ) -> Self {
+        let sources = runs
+            .into_iter()
+            .map(|run| Source {
+                run,
+                next: None,
+                prev: None,
+            })
+            .collect_vec();
+        Self {
+            sources: Some(Box::new(MergedSources {
+                sources,
+                range_tombstones,
+                snapshot,
+                merge_operator,
+                now: current_timestamp(),
+            })),
+            next_entry: None,
+            pending_seek: None,
+            error: None,
+        }
+    }
+
+    /// The error that ended the iteration early, e.g. because a data block could not be read or a
+    /// merged key was read without a merge operator.
+    /// `None` as long as the iterator only stops at the ends of the range.
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    /// Returns the entry after the cursor and moves the cursor forward past it.
+    /// Returns `None` once the end of the range is reached.
+    pub async fn next(&mut self) -> Option<Entry> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    /// Returns the entry before the cursor and moves the cursor backwards past it.
+    /// Returns `None` once the start of the range is reached.
+    pub async fn prev(&mut self) -> Option<Entry> {
+        if self.next_entry.is_some() {
+            // Polled in place, so that the sources are kept should this be cancelled
+            let (sources, entry) =
+                poll_fn(|cx| self.next_entry.as_mut().unwrap().as_mut().poll(cx)).await;
+            self.next_entry = None;
+            // The entry was read, so the cursor moved past it and `prev` returns it below
+            if let Some(Err(error)) = self.finish_next_entry(sources, entry) {
+                self.error = Some(error);
+            }
+        }
+        if self.error.is_some() {
+            return None;
+        }
+        // Unwrap is OK as the sources are only taken while the next entry is read
+        let entry = self.sources.as_mut().unwrap().prev().await;
+        self.record_error(entry)
+    }
+
+    /// Moves the cursor right before the first key that is not smaller than `key`, so that
+    /// [`DbIterator::next`] returns that key next.
+    pub fn seek(
+        &mut self,
+        key: impl AsRef<[u8]>,
+    ) {
+        self.seek_to(Gap::Before(Bytes::copy_from_slice(key.as_ref())));
+    }
+
+    /// Moves the cursor right behind the last key that is not greater than `key`, so that
+    /// [`DbIterator::prev`] returns that key next.
+    pub fn seek_for_prev(
+        &mut self,
+        key: impl AsRef<[u8]>,
+    ) {
+        self.seek_to(Gap::After(Bytes::copy_from_slice(key.as_ref())));
+    }
+
+    /// Moves the cursor to the start of the range.
+    pub fn seek_to_first(&mut self) {
+        self.seek_to(Gap::Start);
+    }
+
+    /// Moves the cursor to the end of the range.
+    pub fn seek_to_last(&mut self) {
+        self.seek_to(Gap::End);
+    }
+
+    fn seek_to(
+        &mut self,
+        gap: Gap,
+    ) {
+        match self.sources.as_mut() {
+            Some(sources) => sources.seek(gap),
+            None => self.pending_seek = Some(gap),
+        }
+    }
+
+    /// Takes back the sources from the entry that was read, dropping the entry if the cursor was
+    /// moved meanwhile.
+    /// Returns the entry otherwise.
+    fn finish_next_entry(
+        &mut self,
+        mut sources: Box<MergedSources>,
+        entry: Result<Option<Entry>>,
+    ) -> Option<Result<Option<Entry>>> {
+        let entry = match self.pending_seek.take() {
+            Some(gap) => {
+                sources.seek(gap);
+                None
+            }
+            None => Some(entry),
+        };
+        self.sources = Some(sources);
+        entry
+    }
+
+    /// Records the error of a step of the iteration, which ends it.
+    fn record_error(
+        &mut self,
+        entry: Result<Option<Entry>>,
+    ) -> Option<Entry> {
+        entry.unwrap_or_else(|error| {
+            self.error = Some(error);
+            None
+        })
+    }
+}
+
+impl Stream for DbIterator {
+    type Item = Entry;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if this.error.is_some() {
+                return Poll::Ready(None);
+            }
+            let next_entry = match this.next_entry.as_mut() {
+                Some(next_entry) => next_entry,
+                None => {
+                    // Unwrap is OK as the sources are only taken while the next entry is read
+                    let sources = this.sources.take().unwrap();
+                    this.next_entry.insert(Box::pin(sources.into_next()))
+                }
+            };
+            let Poll::Ready((sources, entry)) = next_entry.as_mut().poll(cx) else {
+                return Poll::Pending;
+            };
+            this.next_entry = None;
+            // Read again from where the cursor was moved to otherwise
+            if let Some(entry) = this.finish_next_entry(sources, entry) {
+                return Poll::Ready(this.record_error(entry));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::memtable::MemTable;
+    use crate::memtable::MemTableRun;
+    use crate::memtable::MemValue;
+    use crate::memtable::SharedMemTable;
+    use crate::merge_operator::tests::AppendOperator;
+
+    fn runs(entries_by_run: Vec<Vec<(&str, MemValue)>>) -> Vec<Box<dyn SortedRun>> {
+        entries_by_run
+            .into_iter()
+            .map(|entries| {
+                let mut table = MemTable::default();
+                table.extend(
+                    entries
+                        .into_iter()
+                        .map(|(key, value)| (Bytes::copy_from_slice(key.as_bytes()), value)),
+                );
+                let run = MemTableRun::new(
+                    SharedMemTable::from(table),
+                    (Bound::Unbounded, Bound::Unbounded),
+                );
+                Box::new(run) as Box<dyn SortedRun>
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn newest_values_win_and_tombstones_are_hidden() {
+        let newest = vec![("b", MemValue::put(8, "new")), ("c", MemValue::delete(9))];
+        let middle = vec![
+            ("a", MemValue::put(5, "1")),
+            ("c", MemValue::put(6, "2")),
+            ("d", MemValue::delete(7)),
+        ];
+        let oldest = vec![
+            ("b", MemValue::put(1, "old")),
+            ("d", MemValue::put(2, "3")),
+            ("e", MemValue::put(3, "4")),
+        ];
+
+        let entries = DbIterator::new(runs(vec![newest, middle, oldest]), vec![], u64::MAX, None)
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(
+            entries,
+            vec![
+                (Bytes::from("a"), Bytes::from("1")),
+                (Bytes::from("b"), Bytes::from("new")),
+                (Bytes::from("e"), Bytes::from("4")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn versions_newer_than_the_snapshot_are_ignored() {
+        let newest = vec![("a", MemValue::put(4, "new")), ("b", MemValue::delete(5))];
+        let oldest = vec![
+            ("a", MemValue::put(3, "newer")),
+            ("a", MemValue::put(1, "old")),
+            ("b", MemValue::put(2, "1")),
+        ];
+
+        let entries = DbIterator::new(runs(vec![newest, oldest]), vec![], 2, None)
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(
+            entries,
+            vec![
+                (Bytes::from("a"), Bytes::from("old")),
+                (Bytes::from("b"), Bytes::from("1")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn merge_operands_are_applied_across_runs() {
+        let newest = vec![
+            ("a", MemValue::merge(6, "3")),
+            ("b", MemValue::merge(7, "2")),
+        ];
+        let middle = vec![
+            ("a", MemValue::merge(5, "2")),
+            ("a", MemValue::put(4, "1")),
+            ("b", MemValue::delete(3)),
+        ];
+        let oldest = vec![
+            ("a", MemValue::put(2, "old")),
+            ("b", MemValue::put(1, "old")),
+        ];
+        let entries_by_run = vec![newest, middle, oldest];
+
+        let mut iterator = DbIterator::new(
+            runs(entries_by_run.clone()),
+            vec![],
+            u64::MAX,
+            Some(Arc::new(AppendOperator)),
+        );
+        let expected = vec![
+            (Bytes::from("a"), Bytes::from("1,2,3")),
+            (Bytes::from("b"), Bytes::from("2")),
+        ];
+        assert_eq!((&mut iterator).collect::<Vec<_>>().await, expected);
+        assert_eq!(iterator.prev().await, Some(expected[1].clone()));
+        assert_eq!(iterator.prev().await, Some(expected[0].clone()));
+
+        let mut iterator = DbIterator::new(runs(entries_by_run), vec![], u64::MAX, None);
+        assert_eq!(iterator.next().await, None);
+        assert!(matches!(iterator.error(), Some(Error::InvalidArgument(_))));
+        assert_eq!(iterator.prev().await, None);
+    }
+
+    #[tokio::test]
+    async fn keys_deleted_by_range_tombstones_are_skipped() {
+        let newest = vec![("b", MemValue::put(6, "new"))];
+        let oldest = vec![
+            ("a", MemValue::put(1, "1")),
+            ("b", MemValue::put(2, "old")),
+            ("c", MemValue::put(3, "2")),
+            ("d", MemValue::put(4, "3")),
+        ];
+        let range_tombstones = vec![RangeTombstone {
+            start: Bytes::from("b"),
+            end: Bytes::from("d"),
+            seq: 5,
+        }];
+
+        let entries = DbIterator::new(
+            runs(vec![newest.clone(), oldest.clone()]),
+            range_tombstones.clone(),
+            u64::MAX,
+            None,
+        )
+        .collect::<Vec<_>>()
+        .await;
+        assert_eq!(
+            entries,
+            vec![
+                (Bytes::from("a"), Bytes::from("1")),
+                (Bytes::from("b"), Bytes::from("new")),
+                (Bytes::from("d"), Bytes::from("3")),
+            ]
+        );
+
+        // Snapshots taken before the range tombstone still see the deleted keys
+        let entries = DbIterator::new(runs(vec![newest, oldest]), range_tombstones, 4, None)
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(entries, vec!["a", "b", "c", "d"]);
+    }
+
+    fn key_of(entry: Option<(Bytes, Bytes)>) -> Option<Bytes> {
+        entry.map(|(key, _)| key)
+    }
+
+    #[tokio::test]
+    async fn iterator_seeks_and_moves_in_both_directions() {
+        let newest = vec![("b", MemValue::put(5, "new")), ("d", MemValue::delete(6))];
+        let oldest = vec![
+            ("a", MemValue::put(1, "1")),
+            ("b", MemValue::put(2, "old")),
+            ("d", MemValue::put(3, "2")),
+            ("f", MemValue::put(4, "3")),
+        ];
+        let mut iterator = DbIterator::new(runs(vec![newest, oldest]), vec![], u64::MAX, None);
+
+        iterator.seek("c");
+        assert_eq!(key_of(iterator.next().await), Some(Bytes::from("f")));
+        assert_eq!(key_of(iterator.prev().await), Some(Bytes::from("f")));
+        assert_eq!(
+            iterator.prev().await,
+            Some((Bytes::from("b"), Bytes::from("new")))
+        );
+        assert_eq!(key_of(iterator.prev().await), Some(Bytes::from("a")));
+        assert_eq!(iterator.prev().await, None);
+        assert_eq!(key_of(iterator.next().await), Some(Bytes::from("a")));
+
+        iterator.seek_for_prev("d");
+        assert_eq!(key_of(iterator.prev().await), Some(Bytes::from("b")));
+        iterator.seek_for_prev("b");
+        assert_eq!(key_of(iterator.prev().await), Some(Bytes::from("b")));
+        iterator.seek("b");
+        assert_eq!(key_of(iterator.next().await), Some(Bytes::from("b")));
+
+        iterator.seek_to_last();
+        assert_eq!(iterator.next().await, None);
+        assert_eq!(key_of(iterator.prev().await), Some(Bytes::from("f")));
+        iterator.seek_to_first();
+        assert_eq!(key_of(iterator.next().await), Some(Bytes::from("a")));
+    }
+
+    #[test]
+    fn empty_ranges_are_detected() {
+        let range = |start, end| to_key_bounds::<&str, _>((start, end));
+        assert!(is_empty_range(&range(
+            Bound::Included("b"),
+            Bound::Included("a")
+        )));
+        assert!(is_empty_range(&range(
+            Bound::Included("a"),
+            Bound::Excluded("a")
+        )));
+        assert!(!is_empty_range(&range(
+            Bound::Included("a"),
+            Bound::Included("a")
+        )));
+        assert!(!is_empty_range(&range(
+            Bound::Unbounded,
+            Bound::Excluded("a")
+        )));
+    }
+}
@@ -6,12 +6,37 @@
 #![cfg_attr(all(test, feature = "nightly"), feature(test))]
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
+mod blocking;
 mod bloom_filter;
+mod checksum;
+mod column_family;
 mod db;
 mod deserialization;
+mod error;
 mod file_handling;
+mod iterator;
 mod memtable;
+mod merge_operator;
+mod options;
+mod range_tombstone;
 mod serialization;
+mod snapshot;
+mod transaction;
+mod wal;
+mod write_batch;
 
+pub use blocking::BaumDbSync;
+pub use blocking::DbIteratorSync;
+pub use column_family::ColumnFamilyHandle;
 pub use db::BaumDb;
 pub use db::DB;
+pub use error::CorruptionError;
+pub use error::Error;
+pub use error::Result;
+pub use error::TransactionConflictError;
+pub use iterator::DbIterator;
+pub use merge_operator::MergeOperator;
+pub use options::Options;
+pub use snapshot::Snapshot;
+pub use transaction::Transaction;
+pub use write_batch::WriteBatch;
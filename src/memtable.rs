@@ -1,149 +1,622 @@
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::io::Cursor;
-use std::io::Read;
+use std::mem;
+use std::mem::size_of;
 use std::path::Path;
 use std::path::PathBuf;
+use std::slice;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::sync::RwLockReadGuard;
+use std::sync::RwLockWriteGuard;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
-use anyhow::Result;
 use async_trait::async_trait;
 use bytes::Buf;
-use flate2::read::GzDecoder;
+use bytes::Bytes;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
 
+use crate::deserialization::decode_block;
 use crate::deserialization::read_key_value;
 use crate::deserialization::KeyValue;
+use crate::error::Result;
 use crate::file_handling::DataHandling;
+use crate::iterator::Gap;
+use crate::iterator::KeyBounds;
+use crate::iterator::SortedRun;
+use crate::merge_operator::MergeOperator;
+use crate::range_tombstone::apply_range_tombstones;
+use crate::range_tombstone::RangeTombstone;
+
+/// Orders all writes, a later write gets a larger sequence number than an earlier one.
+pub(crate) type SequenceNumber = u64;
+
+/// Milliseconds since the UNIX epoch.
+pub(crate) type Timestamp = u64;
+
+/// The current time as a [`Timestamp`].
+pub(crate) fn current_timestamp() -> Timestamp {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as Timestamp)
+}
 
 #[non_exhaustive]
 #[derive(Debug, Clone)]
 #[cfg_attr(test, derive(PartialEq, Eq))]
 pub(crate) enum MemValue {
-    Put(String),
-    Delete,
+    Put {
+        seq: SequenceNumber,
+        value: Bytes,
+        /// The put reads like a tombstone from this point in time on.
+        expires_at: Option<Timestamp>,
+    },
+    Delete {
+        seq: SequenceNumber,
+    },
+    /// An operand combined with the older versions of the key by the [`MergeOperator`].
+    Merge {
+        seq: SequenceNumber,
+        operand: Bytes,
+    },
+}
+
+impl MemValue {
+    /// The sequence number of the write that produced the value.
+    pub(crate) fn seq(&self) -> SequenceNumber {
+        match self {
+            MemValue::Put { seq, .. } | MemValue::Delete { seq } | MemValue::Merge { seq, .. } => {
+                *seq
+            }
+        }
+    }
+
+    pub(crate) fn is_merge_operand(&self) -> bool {
+        matches!(self, MemValue::Merge { .. })
+    }
+
+    /// The approximate number of bytes the value occupies in a memtable.
+    fn approximate_size(&self) -> usize {
+        let payload = match self {
+            MemValue::Put { value, .. } => value.len(),
+            MemValue::Delete { .. } => 0,
+            MemValue::Merge { operand, .. } => operand.len(),
+        };
+        size_of::<MemValue>() + payload
+    }
+
+    /// Whether the value is a put that expired at or before `now`.
+    pub(crate) fn is_expired(
+        &self,
+        now: Timestamp,
+    ) -> bool {
+        matches!(self, MemValue::Put { expires_at: Some(expires_at), .. } if *expires_at <= now)
+    }
+}
+
+#[cfg(test)]
+impl MemValue {
+    pub(crate) fn put(
+        seq: SequenceNumber,
+        value: &str,
+    ) -> Self {
+        MemValue::Put {
+            seq,
+            value: Bytes::copy_from_slice(value.as_bytes()),
+            expires_at: None,
+        }
+    }
+
+    pub(crate) fn delete(seq: SequenceNumber) -> Self {
+        MemValue::Delete { seq }
+    }
+
+    pub(crate) fn merge(
+        seq: SequenceNumber,
+        operand: &str,
+    ) -> Self {
+        MemValue::Merge {
+            seq,
+            operand: Bytes::copy_from_slice(operand.as_bytes()),
+        }
+    }
+
+    /// The value written by a put, `None` for other versions.
+    pub(crate) fn into_put_value(self) -> Option<Bytes> {
+        match self {
+            MemValue::Put { value, .. } => Some(value),
+            _ => None,
+        }
+    }
 }
 
-type MemTableBase = BTreeMap<String, MemValue>;
+/// A single write of a batch, as it is logged and applied to the memtable.
+#[derive(Debug, Clone)]
+pub(crate) enum WriteEntry {
+    Version(Bytes, MemValue),
+    RangeTombstone(RangeTombstone),
+}
+
+/// The versions of a key, ordered from newest to oldest.
+pub(crate) type Versions = Vec<MemValue>;
+
+/// Collects the versions visible at the snapshot that are needed to read a key: the newest one
+/// and, as long as those are merge operands, the older ones they are applied to.
+pub(crate) fn versions_to_read<'a>(
+    versions: impl IntoIterator<Item = &'a MemValue>,
+    snapshot: SequenceNumber,
+) -> Versions {
+    let mut to_read = Versions::new();
+    for value in versions {
+        if value.seq() > snapshot {
+            continue;
+        }
+        to_read.push(value.clone());
+        if !value.is_merge_operand() {
+            break;
+        }
+    }
+    to_read
+}
+
+/// Whether the versions, ordered from newest to oldest, end in a value that older versions cannot
+/// change anymore.
+pub(crate) fn is_complete(versions: &Versions) -> bool {
+    versions
+        .last()
+        .is_some_and(|value| !value.is_merge_operand())
+}
+
+/// Appends the versions of a key read from an older memtable or SST bundle to the ones read from
+/// the newer ones, both ordered from newest to oldest.
+/// Versions that are not older than the oldest one collected so far are skipped: they were read
+/// before already, but a flush running concurrently with the read moved them to an SST bundle.
+pub(crate) fn extend_with_older_versions(
+    versions: &mut Versions,
+    older_versions: impl IntoIterator<Item = MemValue>,
+) {
+    let oldest_seq = versions.last().map(MemValue::seq);
+    versions.extend(
+        older_versions
+            .into_iter()
+            .filter(|value| oldest_seq.is_none_or(|seq| value.seq() < seq)),
+    );
+}
+
+type MemTableBase = BTreeMap<Bytes, Versions>;
+
+/// The approximate number of bytes a key occupies in a memtable besides its versions, i.e. its
+/// node in the tree and the vector of its versions.
+const KEY_OVERHEAD: usize = 2 * size_of::<Bytes>() + size_of::<Versions>();
+
+/// The approximate number of bytes a key with its versions occupies in a memtable.
+fn approximate_key_size(
+    key: &[u8],
+    versions: &[MemValue],
+) -> usize {
+    KEY_OVERHEAD
+        + key.len()
+        + versions
+            .iter()
+            .map(MemValue::approximate_size)
+            .sum::<usize>()
+}
+
+/// The approximate number of bytes the range tombstones occupy in a memtable.
+fn approximate_range_tombstones_size(range_tombstones: &[RangeTombstone]) -> usize {
+    range_tombstones
+        .iter()
+        .map(|tombstone| size_of::<RangeTombstone>() + tombstone.start.len() + tombstone.end.len())
+        .sum()
+}
 
 /// The main MemTable struct.
+/// It holds every version of a key written to it, so that reads of older snapshots can be served.
 #[derive(Default, Debug, Clone)]
-pub(crate) struct MemTable(MemTableBase);
+pub(crate) struct MemTable {
+    entries: MemTableBase,
+    n_versions: usize,
+    // The approximate memory footprint of the entries in bytes. Range tombstones are few, so their
+    // footprint is computed when needed.
+    entries_size: usize,
+    range_tombstones: Vec<RangeTombstone>,
+}
 
-/// A secondary MemTable struct that only allows reading from.
+/// A memtable shared by the column family and the iterators reading it.
+///
+/// The iterators keep reading the table they were created with, even after it was flushed and
+/// replaced by a new one.
 #[derive(Default, Debug, Clone)]
-pub(crate) struct MemTableReadOnly(MemTableBase);
+pub(crate) struct SharedMemTable(Arc<RwLock<MemTable>>);
+
+impl From<MemTable> for SharedMemTable {
+    fn from(table: MemTable) -> Self {
+        Self(Arc::new(RwLock::new(table)))
+    }
+}
+
+impl SharedMemTable {
+    pub(crate) fn read(&self) -> RwLockReadGuard<'_, MemTable> {
+        // Unwrap is OK as the lock is never held while panicking
+        self.0.read().unwrap()
+    }
+
+    pub(crate) fn write(&self) -> RwLockWriteGuard<'_, MemTable> {
+        self.0.write().unwrap()
+    }
+}
 
 impl MemTable {
-    /// The size of the MemTable
+    /// The number of versions and range tombstones stored in the table.
     pub(crate) fn len(&self) -> usize {
-        // TODO adjust this to take the value size of the table into account, not just the
-        // number of entries.
-        self.0.len()
+        self.n_versions + self.range_tombstones.len()
     }
 
-    pub(crate) fn into_inner(self) -> MemTableBase {
-        self.0
+    /// The approximate memory footprint of the table in bytes, counting keys, values, tombstones,
+    /// range tombstones and the overhead of the nodes holding them.
+    pub(crate) fn approximate_size(&self) -> usize {
+        self.entries_size + approximate_range_tombstones_size(&self.range_tombstones)
     }
-}
 
-impl IntoIterator for MemTable {
-    type IntoIter = std::collections::btree_map::IntoIter<String, MemValue>;
-    type Item = (String, MemValue);
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
+    /// The largest sequence number stored in the table.
+    pub(crate) fn largest_seq(&self) -> Option<SequenceNumber> {
+        self.iter()
+            .map(|(_, value)| value.seq())
+            .chain(self.range_tombstones.iter().map(|tombstone| tombstone.seq))
+            .max()
+    }
+
+    /// Iterates over all versions in key order, newest version of a key first.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&Bytes, &MemValue)> {
+        self.entries
+            .iter()
+            .flat_map(|(key, versions)| versions.iter().map(move |value| (key, value)))
+    }
+
+    /// Like [`MemTable::iter`], but only over the versions in the non-empty key range.
+    /// Range tombstones are not applied to them.
+    pub(crate) fn scan(
+        &self,
+        range: &KeyBounds,
+    ) -> impl Iterator<Item = (&Bytes, &MemValue)> {
+        self.entries
+            .range::<Bytes, _>(range.clone())
+            .flat_map(|(key, versions)| versions.iter().map(move |value| (key, value)))
+    }
+
+    /// Consumes the table, returning all versions in key order, newest version of a key first.
+    /// The range tombstones are not included.
+    pub(crate) fn into_entries(self) -> impl Iterator<Item = (Bytes, MemValue)> {
+        self.entries
+            .into_iter()
+            .flat_map(|(key, versions)| versions.into_iter().map(move |value| (key.clone(), value)))
+    }
+
+    /// Lets `f` drop versions of each key, passing it the versions ordered from newest to oldest.
+    /// Keys without any versions left are removed.
+    pub(crate) fn retain_versions<F>(
+        &mut self,
+        mut f: F,
+    ) where
+        F: FnMut(&Bytes, &mut Versions),
+    {
+        let mut n_versions = 0;
+        let mut entries_size = 0;
+        self.entries.retain(|key, versions| {
+            f(key, versions);
+            n_versions += versions.len();
+            if versions.is_empty() {
+                return false;
+            }
+            entries_size += approximate_key_size(key, versions);
+            true
+        });
+        self.n_versions = n_versions;
+        self.entries_size = entries_size;
+    }
+
+    /// Lets `f` drop range tombstones.
+    pub(crate) fn retain_range_tombstones<F>(
+        &mut self,
+        f: F,
+    ) where
+        F: FnMut(&RangeTombstone) -> bool,
+    {
+        self.range_tombstones.retain(f);
+    }
+
+    /// Drops all versions that are neither the newest version of their key nor visible to any of
+    /// the live snapshots. Versions a retained merge operand is applied to are kept as well.
+    /// Versions deleted by a range tombstone of the table are only kept for the snapshots taken
+    /// before the tombstone.
+    pub(crate) fn retain_visible_versions(
+        &mut self,
+        live_snapshots: &BTreeSet<SequenceNumber>,
+    ) {
+        let range_tombstones = mem::take(&mut self.range_tombstones);
+        self.retain_versions(|key, versions| {
+            let mut newer_version: Option<(SequenceNumber, bool)> = None;
+            versions.retain(|value| {
+                // The oldest range tombstone deleting the version hides it like a newer version
+                let deleted_at = range_tombstones
+                    .iter()
+                    .filter(|tombstone| tombstone.covers(key, value.seq()))
+                    .map(|tombstone| tombstone.seq)
+                    .min();
+                // A version is read by the snapshots taken after it was written but before the next
+                // newer version was.
+                let is_visible = match (newer_version, deleted_at) {
+                    (Some((_, true)), _) => true,
+                    (None, None) => true,
+                    (None, Some(hidden_at)) | (Some((hidden_at, false)), None) => live_snapshots
+                        .range(value.seq()..hidden_at)
+                        .next()
+                        .is_some(),
+                    (Some((newer_seq, false)), Some(deleted_at)) => live_snapshots
+                        .range(value.seq()..newer_seq.min(deleted_at))
+                        .next()
+                        .is_some(),
+                };
+                newer_version = Some((value.seq(), value.is_merge_operand()));
+                is_visible
+            })
+        });
+        self.range_tombstones = range_tombstones;
+    }
+
+    /// Replaces the puts that expired at or before `now` by tombstones, which still shadow older
+    /// versions of their key.
+    pub(crate) fn expire_versions(
+        &mut self,
+        now: Timestamp,
+    ) {
+        self.retain_versions(|_, versions| {
+            for version in versions.iter_mut() {
+                if version.is_expired(now) {
+                    *version = MemValue::Delete { seq: version.seq() };
+                }
+            }
+        })
     }
-}
 
-impl IntoIterator for MemTableReadOnly {
-    type IntoIter = std::collections::btree_map::IntoIter<String, MemValue>;
-    type Item = (String, MemValue);
+    /// Applies merge operands to the older put, tombstone or range tombstone of their key,
+    /// replacing each operand by a put of the value read at its sequence number.
+    /// Operands of keys without such a version in the table are only applied if
+    /// `has_no_older_data` tells that the key is not stored anywhere else either.
+    pub(crate) fn resolve_merge_operands<F>(
+        &mut self,
+        merge_operator: &dyn MergeOperator,
+        mut has_no_older_data: F,
+    ) where
+        F: FnMut(&Bytes) -> bool,
+    {
+        let range_tombstones = mem::take(&mut self.range_tombstones);
+        self.retain_versions(|key, versions| {
+            if !versions.iter().any(MemValue::is_merge_operand) {
+                return;
+            }
+            // `None` as long as the value the operands are applied to is unknown
+            let mut existing_value = has_no_older_data(key).then_some(None);
+            let mut older_seq = None;
+            for version in versions.iter_mut().rev() {
+                // A range tombstone written between the older and this version deletes the key
+                let is_deleted_by_range = range_tombstones.iter().any(|tombstone| {
+                    tombstone.seq < version.seq()
+                        && older_seq.is_none_or(|older_seq| older_seq < tombstone.seq)
+                        && tombstone.contains(key)
+                });
+                if is_deleted_by_range {
+                    existing_value = Some(None);
+                }
+                older_seq = Some(version.seq());
+                match version {
+                    // Whether a value that expires is read depends on the time of the read
+                    MemValue::Put {
+                        expires_at: Some(_),
+                        ..
+                    } => existing_value = None,
+                    MemValue::Put { value, .. } => existing_value = Some(Some(value.clone())),
+                    MemValue::Delete { .. } => existing_value = Some(None),
+                    MemValue::Merge { seq, operand } => {
+                        if let Some(existing) = &existing_value {
+                            let merged = merge_operator.merge(
+                                key,
+                                existing.as_deref(),
+                                slice::from_ref(operand),
+                            );
+                            *version = MemValue::Put {
+                                seq: *seq,
+                                value: merged.clone(),
+                                expires_at: None,
+                            };
+                            existing_value = Some(Some(merged));
+                        }
+                    }
+                }
+            }
+        });
+        self.range_tombstones = range_tombstones;
+    }
+}
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
+impl Extend<(Bytes, MemValue)> for MemTable {
+    fn extend<T: IntoIterator<Item = (Bytes, MemValue)>>(
+        &mut self,
+        iter: T,
+    ) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
     }
 }
 
 pub(crate) trait MemTableGet {
+    /// Gets the versions stored for the key that are needed to read it at the snapshot, newest
+    /// first, see [`versions_to_read`].
+    /// A tombstone is returned as well as it shadows any older value of the key. So is a range
+    /// tombstone containing the key, in the form of a point tombstone.
     fn get(
         &self,
-        key: &str,
-    ) -> Result<Option<String>>;
+        key: &[u8],
+        snapshot: SequenceNumber,
+    ) -> Result<Versions>;
+
+    /// The range tombstones stored in the table.
+    fn range_tombstones(&self) -> &[RangeTombstone];
 }
 
 impl MemTableGet for MemTable {
     fn get(
         &self,
-        key: &str,
-    ) -> Result<Option<String>> {
-        memtable_get_inner(&self.0, key)
+        key: &[u8],
+        snapshot: SequenceNumber,
+    ) -> Result<Versions> {
+        memtable_get_inner(&self.entries, &self.range_tombstones, key, snapshot)
     }
-}
 
-impl MemTableGet for MemTableReadOnly {
-    fn get(
-        &self,
-        key: &str,
-    ) -> Result<Option<String>> {
-        memtable_get_inner(&self.0, key)
+    fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
     }
 }
 
 pub(crate) trait MemTableWrite {
-    fn put(
+    /// Inserts a version of the key.
+    fn insert(
         &mut self,
-        key: String,
-        value: String,
-    ) -> Result<()>;
+        key: Bytes,
+        value: MemValue,
+    );
 
-    fn delete(
+    /// Inserts a range tombstone, which deletes the older versions of all keys in its range.
+    fn insert_range_tombstone(
         &mut self,
-        key: &str,
-    ) -> Result<()>;
+        tombstone: RangeTombstone,
+    );
+
+    /// Applies a single write of a batch.
+    fn apply(
+        &mut self,
+        entry: WriteEntry,
+    ) {
+        match entry {
+            WriteEntry::Version(key, value) => self.insert(key, value),
+            WriteEntry::RangeTombstone(tombstone) => self.insert_range_tombstone(tombstone),
+        }
+    }
 }
 
 impl MemTableWrite for MemTable {
-    fn put(
+    fn insert(
         &mut self,
-        key: String,
-        value: String,
-    ) -> Result<()> {
-        self.0.insert(key, MemValue::Put(value));
-        Ok(())
+        key: Bytes,
+        value: MemValue,
+    ) {
+        // The key may be present in the SSTables on disk even if it is in the memtable as well, so
+        // tombstones must always be kept to shadow older values.
+        if !self.entries.contains_key(&key) {
+            self.entries_size += KEY_OVERHEAD + key.len();
+        }
+        let versions = self.entries.entry(key).or_default();
+        let position = versions.partition_point(|existing| existing.seq() > value.seq());
+        // A log replayed twice holds the same versions again, which must not be applied twice
+        if versions
+            .get(position)
+            .is_some_and(|existing| existing.seq() == value.seq())
+        {
+            return;
+        }
+        self.entries_size += value.approximate_size();
+        versions.insert(position, value);
+        self.n_versions += 1;
     }
 
-    fn delete(
+    fn insert_range_tombstone(
         &mut self,
-        key: &str,
-    ) -> Result<()> {
-        if self.0.remove(key).is_none() {
-            // Key was not present in the memtable, but may be present in the SSTables on disk, so
-            // let's add a tombstone just in case.
-            self.0.insert(key.to_string(), MemValue::Delete);
+        tombstone: RangeTombstone,
+    ) {
+        if self
+            .range_tombstones
+            .iter()
+            .any(|existing| existing.seq == tombstone.seq)
+        {
+            return;
         }
-        Ok(())
+        self.range_tombstones.push(tombstone);
     }
 }
 
 fn memtable_get_inner(
     base_table: &MemTableBase,
-    key: &str,
-) -> Result<Option<String>> {
-    Ok(base_table.get(key).and_then(|v| match v {
-        MemValue::Put(str) => Some(str.to_string()),
-        MemValue::Delete => None,
-    }))
+    range_tombstones: &[RangeTombstone],
+    key: &[u8],
+    snapshot: SequenceNumber,
+) -> Result<Versions> {
+    let versions = base_table
+        .get(key)
+        .map(|versions| versions_to_read(versions, snapshot))
+        .unwrap_or_default();
+    Ok(apply_range_tombstones(
+        versions,
+        range_tombstones,
+        key,
+        snapshot,
+    ))
+}
+
+/// A cursor over the keys of a memtable in a key range, see [`SortedRun`].
+/// It looks up the key next to it on every read, so it never copies more than a single entry.
+#[derive(Debug)]
+pub(crate) struct MemTableRun {
+    table: SharedMemTable,
+    range: KeyBounds,
+    gap: Gap,
 }
 
-impl From<MemTable> for MemTableReadOnly {
-    fn from(value: MemTable) -> Self {
-        Self(value.0)
+impl MemTableRun {
+    pub(crate) fn new(
+        table: SharedMemTable,
+        range: KeyBounds,
+    ) -> Self {
+        Self {
+            table,
+            range,
+            gap: Gap::Start,
+        }
     }
 }
 
-impl From<MemTableBase> for MemTable {
-    fn from(value: MemTableBase) -> Self {
-        Self(value)
+#[async_trait]
+impl SortedRun for MemTableRun {
+    async fn read_next(&mut self) -> Result<Option<(Bytes, Versions)>> {
+        let Some(bounds) = self.gap.bounds_after(&self.range) else {
+            return Ok(None);
+        };
+        let table = self.table.read();
+        let entry = table.entries.range::<Bytes, _>(bounds).next();
+        Ok(entry.map(|(key, versions)| (key.clone(), versions.clone())))
+    }
+
+    async fn read_prev(&mut self) -> Result<Option<(Bytes, Versions)>> {
+        let Some(bounds) = self.gap.bounds_before(&self.range) else {
+            return Ok(None);
+        };
+        let table = self.table.read();
+        let entry = table.entries.range::<Bytes, _>(bounds).next_back();
+        Ok(entry.map(|(key, versions)| (key.clone(), versions.clone())))
+    }
+
+    fn seek(
+        &mut self,
+        gap: Gap,
+    ) {
+        self.gap = gap;
     }
 }
 
@@ -156,28 +629,214 @@ impl DataHandling for MemTable {
         P: Into<PathBuf>,
         P: Send,
     {
+        let path = path.as_ref();
         let mut memtable_file = File::open(path).await?;
         let mut memtable_bytes = Vec::<u8>::new();
         memtable_file.read_to_end(&mut memtable_bytes).await?;
-        let mut raw_table: MemTableBase = BTreeMap::new();
+        let mut table = MemTable::default();
 
         let mut memtable_bytes = Cursor::new(memtable_bytes);
 
         while memtable_bytes.has_remaining() {
+            let block_offset = memtable_bytes.position();
             let encoded_block_length = memtable_bytes.read_u64().await? as usize;
+            let block_checksum = memtable_bytes.read_u32().await?;
             let mut raw_block = vec![0; encoded_block_length];
             std::io::Read::read_exact(&mut memtable_bytes, &mut raw_block)?;
 
-            let mut decoder = GzDecoder::new(raw_block.as_slice());
-            // The vec will very likely end up larger than the `n_bytes_to_read`,
-            // but that's the best we know at this point and it'll save some reallocations.
-            let mut decompressed_block = Vec::with_capacity(raw_block.len());
-            decoder.read_to_end(&mut decompressed_block)?;
+            let decompressed_block = decode_block(&raw_block, block_checksum, path, block_offset)?;
             let mut decompressed_cursor = Cursor::new(decompressed_block);
             while let Ok(KeyValue { key, value }) = read_key_value(&mut decompressed_cursor) {
-                raw_table.insert(key, value);
+                table.insert(key, value);
             }
         }
-        Ok(MemTable(raw_table))
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge_operator::tests::AppendOperator;
+
+    #[test]
+    fn reads_see_the_newest_version_of_their_snapshot() {
+        let mut table = MemTable::default();
+        table.insert(Bytes::from("key"), MemValue::put(3, "3"));
+        table.insert(Bytes::from("key"), MemValue::put(1, "1"));
+        table.insert(Bytes::from("key"), MemValue::delete(5));
+        assert_eq!(table.len(), 3);
+
+        assert_eq!(table.get(b"key", 0).unwrap(), vec![]);
+        assert_eq!(table.get(b"key", 2).unwrap(), vec![MemValue::put(1, "1")]);
+        assert_eq!(table.get(b"key", 4).unwrap(), vec![MemValue::put(3, "3")]);
+        assert_eq!(table.get(b"key", 5).unwrap(), vec![MemValue::delete(5)]);
+    }
+
+    #[test]
+    fn merge_operands_are_read_together_with_their_base_value() {
+        let mut table = MemTable::default();
+        table.insert(Bytes::from("key"), MemValue::put(1, "1"));
+        table.insert(Bytes::from("key"), MemValue::put(2, "2"));
+        table.insert(Bytes::from("key"), MemValue::merge(3, "3"));
+        table.insert(Bytes::from("key"), MemValue::merge(4, "4"));
+
+        assert_eq!(
+            table.get(b"key", 4).unwrap(),
+            vec![
+                MemValue::merge(4, "4"),
+                MemValue::merge(3, "3"),
+                MemValue::put(2, "2")
+            ]
+        );
+        assert_eq!(table.get(b"key", 2).unwrap(), vec![MemValue::put(2, "2")]);
+    }
+
+    #[test]
+    fn merge_operands_are_resolved_only_with_known_base_value() {
+        let mut table = MemTable::default();
+        table.insert(Bytes::from("based"), MemValue::put(1, "a"));
+        table.insert(Bytes::from("based"), MemValue::merge(2, "b"));
+        table.insert(Bytes::from("based"), MemValue::merge(3, "c"));
+        table.insert(Bytes::from("unbased"), MemValue::merge(4, "d"));
+        table.insert(Bytes::from("unbased"), MemValue::merge(5, "e"));
+
+        table.resolve_merge_operands(&AppendOperator, |_| false);
+        table.retain_visible_versions(&BTreeSet::new());
+
+        let retained = table
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            retained,
+            vec![
+                (Bytes::from("based"), MemValue::put(3, "a,b,c")),
+                (Bytes::from("unbased"), MemValue::merge(5, "e")),
+                (Bytes::from("unbased"), MemValue::merge(4, "d")),
+            ]
+        );
+
+        table.resolve_merge_operands(&AppendOperator, |_| true);
+        assert_eq!(
+            table.get(b"unbased", SequenceNumber::MAX).unwrap(),
+            vec![MemValue::put(5, "d,e")]
+        );
+    }
+
+    #[test]
+    fn merge_operands_after_a_range_tombstone_start_from_scratch() {
+        let mut table = MemTable::default();
+        table.insert(Bytes::from("key"), MemValue::put(1, "a"));
+        table.insert(Bytes::from("key"), MemValue::merge(3, "b"));
+        table.insert(Bytes::from("key"), MemValue::merge(5, "c"));
+        table.insert_range_tombstone(RangeTombstone {
+            start: Bytes::from("a"),
+            end: Bytes::from("z"),
+            seq: 2,
+        });
+        // Newer than all versions, it must not affect how the operands are resolved
+        table.insert_range_tombstone(RangeTombstone {
+            start: Bytes::from("a"),
+            end: Bytes::from("z"),
+            seq: 6,
+        });
+
+        table.resolve_merge_operands(&AppendOperator, |_| false);
+
+        assert_eq!(table.get(b"key", 5).unwrap(), vec![MemValue::put(5, "b,c")]);
+        assert_eq!(table.get(b"key", 3).unwrap(), vec![MemValue::put(3, "b")]);
+        assert_eq!(table.get(b"key", 1).unwrap(), vec![MemValue::put(1, "a")]);
+    }
+
+    #[test]
+    fn versions_deleted_by_range_tombstones_are_only_retained_for_snapshots() {
+        let mut table = MemTable::default();
+        for seq in [1, 2, 3, 5] {
+            table.insert(Bytes::from("key"), MemValue::put(seq, "value"));
+        }
+        table.insert_range_tombstone(RangeTombstone {
+            start: Bytes::from("k"),
+            end: Bytes::from("l"),
+            seq: 4,
+        });
+
+        table.retain_visible_versions(&BTreeSet::from([2]));
+
+        let retained = table
+            .iter()
+            .map(|(_, value)| value.seq())
+            .collect::<Vec<_>>();
+        assert_eq!(retained, vec![5, 2]);
+        assert_eq!(table.range_tombstones().len(), 1);
+        assert_eq!(table.get(b"key", 4).unwrap(), vec![MemValue::delete(4)]);
+    }
+
+    #[test]
+    fn only_versions_visible_to_live_snapshots_are_retained() {
+        let mut table = MemTable::default();
+        for seq in 1..=6 {
+            table.insert(Bytes::from("key"), MemValue::put(seq, "value"));
+        }
+        table.insert(Bytes::from("other"), MemValue::put(7, "value"));
+
+        table.retain_visible_versions(&BTreeSet::from([2, 3, 4]));
+
+        let retained = table
+            .iter()
+            .map(|(key, value)| (key.clone(), value.seq()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            retained,
+            vec![
+                (Bytes::from("key"), 6),
+                (Bytes::from("key"), 4),
+                (Bytes::from("key"), 3),
+                (Bytes::from("key"), 2),
+                (Bytes::from("other"), 7),
+            ]
+        );
+        assert_eq!(table.len(), 5);
+    }
+
+    #[test]
+    fn size_accounts_for_keys_values_and_range_tombstones() {
+        let mut table = MemTable::default();
+        assert_eq!(table.approximate_size(), 0);
+
+        table.insert(Bytes::from("key"), MemValue::put(1, "small"));
+        let one_version = table.approximate_size();
+        assert!(one_version >= "key".len() + "small".len());
+
+        // A second version of the key only adds the version itself
+        table.insert(Bytes::from("key"), MemValue::put(2, "small"));
+        assert_eq!(
+            table.approximate_size(),
+            one_version + MemValue::put(2, "small").approximate_size()
+        );
+
+        table.insert(
+            Bytes::from("large"),
+            MemValue::Put {
+                seq: 3,
+                value: Bytes::from(vec![0; 1 << 20]),
+                expires_at: None,
+            },
+        );
+        assert!(table.approximate_size() > 1 << 20);
+
+        table.insert_range_tombstone(RangeTombstone {
+            start: Bytes::from("a"),
+            end: Bytes::from("z"),
+            seq: 4,
+        });
+        let with_tombstone = table.approximate_size();
+
+        // Dropping versions shrinks the table again
+        table.retain_visible_versions(&BTreeSet::new());
+        assert!(table.approximate_size() < with_tombstone);
+        table.retain_versions(|_, versions| versions.clear());
+        table.retain_range_tombstones(|_| false);
+        assert_eq!(table.approximate_size(), 0);
     }
 }
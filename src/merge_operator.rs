@@ -0,0 +1,151 @@
+use std::fmt::Debug;
+
+use bytes::Bytes;
+
+use crate::error::Error;
+use crate::error::Result;
+use crate::memtable::MemValue;
+use crate::memtable::Timestamp;
+
+/// Combines the merge operands written with [`DB::merge`](crate::DB::merge) into a value.
+///
+/// Operands are stored as they are written and only combined when the key is read, or when the
+/// value they are applied to is compacted or flushed together with them. This makes read-modify-write
+/// updates like counters or appends possible without reading the current value first.
+///
+/// Operands may be combined in several steps, so applying them one by one must give the same
+/// result as applying all of them at once.
+pub trait MergeOperator: Debug + Send + Sync {
+    /// Applies the operands, ordered from oldest to newest, to the existing value of the key.
+    /// `existing_value` is `None` if the key was never written or has been deleted.
+    fn merge(
+        &self,
+        key: &[u8],
+        existing_value: Option<&[u8]>,
+        operands: &[Bytes],
+    ) -> Bytes;
+}
+
+/// Reads the value of the key at time `now` from the versions ordered from newest to oldest.
+///
+/// The newest version determines the value, unless it is a merge operand. Then the operands up to
+/// the next older put or tombstone are applied to it. Expired puts read like tombstones.
+pub(crate) fn resolve_versions(
+    key: &[u8],
+    versions: impl IntoIterator<Item = MemValue>,
+    merge_operator: Option<&dyn MergeOperator>,
+    now: Timestamp,
+) -> Result<Option<Bytes>> {
+    let mut operands = Vec::new();
+    let mut existing_value = None;
+    for version in versions {
+        if version.is_expired(now) {
+            break;
+        }
+        match version {
+            MemValue::Merge { operand, .. } => operands.push(operand),
+            MemValue::Put { value, .. } => {
+                existing_value = Some(value);
+                break;
+            }
+            MemValue::Delete { .. } => break,
+        }
+    }
+    if operands.is_empty() {
+        return Ok(existing_value);
+    }
+    let merge_operator = merge_operator.ok_or_else(|| {
+        Error::InvalidArgument("No merge operator configured to read merged key".to_string())
+    })?;
+    operands.reverse();
+    Ok(Some(merge_operator.merge(
+        key,
+        existing_value.as_deref(),
+        &operands,
+    )))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Appends the operands to the existing value, separated by commas.
+    #[derive(Debug)]
+    pub(crate) struct AppendOperator;
+
+    impl MergeOperator for AppendOperator {
+        fn merge(
+            &self,
+            _key: &[u8],
+            existing_value: Option<&[u8]>,
+            operands: &[Bytes],
+        ) -> Bytes {
+            let mut parts = existing_value
+                .into_iter()
+                .chain(operands.iter().map(Bytes::as_ref));
+            let mut merged = parts.next().unwrap_or_default().to_vec();
+            for part in parts {
+                merged.push(b',');
+                merged.extend_from_slice(part);
+            }
+            Bytes::from(merged)
+        }
+    }
+
+    #[test]
+    fn operands_are_applied_to_the_newest_older_value() {
+        let versions = vec![
+            MemValue::merge(5, "c"),
+            MemValue::merge(4, "b"),
+            MemValue::put(3, "a"),
+            MemValue::merge(2, "ignored"),
+        ];
+        assert_eq!(
+            resolve_versions(b"key", versions, Some(&AppendOperator), 0).unwrap(),
+            Some(Bytes::from("a,b,c"))
+        );
+
+        let versions = vec![
+            MemValue::merge(5, "c"),
+            MemValue::delete(4),
+            MemValue::merge(3, "b"),
+        ];
+        assert_eq!(
+            resolve_versions(b"key", versions, Some(&AppendOperator), 0).unwrap(),
+            Some(Bytes::from("c"))
+        );
+    }
+
+    #[test]
+    fn expired_puts_read_like_tombstones() {
+        let versions = vec![
+            MemValue::merge(3, "b"),
+            MemValue::Put {
+                seq: 2,
+                value: Bytes::from("a"),
+                expires_at: Some(100),
+            },
+        ];
+        assert_eq!(
+            resolve_versions(b"key", versions.clone(), Some(&AppendOperator), 99).unwrap(),
+            Some(Bytes::from("a,b"))
+        );
+        assert_eq!(
+            resolve_versions(b"key", versions.clone(), Some(&AppendOperator), 100).unwrap(),
+            Some(Bytes::from("b"))
+        );
+        assert_eq!(
+            resolve_versions(b"key", versions.into_iter().skip(1), None, 100).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn operands_cannot_be_read_without_merge_operator() {
+        assert!(resolve_versions(b"key", vec![MemValue::merge(1, "a")], None, 0).is_err());
+        assert_eq!(
+            resolve_versions(b"key", vec![MemValue::delete(1)], None, 0).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use tokio::fs::read_to_string;
+use tokio::fs::rename;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+use crate::error::Error;
+use crate::error::Result;
+use crate::file_handling::sync_dir;
+use crate::merge_operator::MergeOperator;
+
+const OPTIONS_FILE_NAME: &str = "OPTIONS";
+const OPTIONS_TMP_FILE_NAME: &str = "OPTIONS.tmp";
+
+/// The settings a [`BaumDb`](crate::BaumDb) is opened with.
+///
+/// Every setting has a default, so only the ones that differ need to be set. They are validated
+/// when the database is opened and recorded in its `OPTIONS` file. When the database is reopened,
+/// the settings that are not set are taken from that file instead of their defaults.
+///
+/// All settings may be set differently on every open, as the SST bundles written before describe
+/// their own layout. Only a database that was opened with a merge operator must always be opened
+/// with one again, as it may contain merge operands, or opening fails with
+/// [`Error::InvalidArgument`].
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub(crate) max_memtable_size: usize,
+    pub(crate) write_buffer_size: usize,
+    pub(crate) block_size: usize,
+    pub(crate) compression_level: u32,
+    pub(crate) bloom_filter_size: usize,
+    pub(crate) bloom_filter_hashes: u8,
+    pub(crate) l0_compaction_threshold: usize,
+    pub(crate) l1_compaction_threshold: usize,
+    pub(crate) flush_queue_depth: usize,
+    pub(crate) compaction_queue_depth: usize,
+    pub(crate) merge_operator: Option<Arc<dyn MergeOperator>>,
+    // The names of the settings that were set, the others are taken from the `OPTIONS` file
+    explicitly_set: BTreeSet<&'static str>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            max_memtable_size: usize::MAX,
+            write_buffer_size: 64 << 20,
+            block_size: 4096,
+            compression_level: 6,
+            bloom_filter_size: 65536,
+            bloom_filter_hashes: 5,
+            l0_compaction_threshold: 4,
+            l1_compaction_threshold: 8,
+            flush_queue_depth: 1,
+            compaction_queue_depth: 1,
+            merge_operator: None,
+            explicitly_set: BTreeSet::new(),
+        }
+    }
+}
+
+impl Options {
+    /// An upper limit on the number of versions and range tombstones a memtable holds. It is
+    /// flushed to disk once it holds that many entries, even if it has not reached
+    /// [`Options::write_buffer_size`] yet.
+    /// Not limited by default, so that only the memory footprint of a memtable triggers its flush.
+    /// Column families opened with their own limit use that one instead.
+    pub fn max_memtable_size(
+        mut self,
+        max_memtable_size: usize,
+    ) -> Self {
+        self.max_memtable_size = max_memtable_size;
+        self.explicitly_set.insert("max_memtable_size");
+        self
+    }
+
+    /// The approximate number of bytes of memory after which a memtable is flushed to disk, no
+    /// matter how many entries it holds, see also [`Options::max_memtable_size`].
+    pub fn write_buffer_size(
+        mut self,
+        write_buffer_size: usize,
+    ) -> Self {
+        self.write_buffer_size = write_buffer_size;
+        self.explicitly_set.insert("write_buffer_size");
+        self
+    }
+
+    /// The number of uncompressed bytes after which a data block of an SST bundle is completed.
+    /// All versions of a key are stored in the same block, so blocks may be larger.
+    pub fn block_size(
+        mut self,
+        block_size: usize,
+    ) -> Self {
+        self.block_size = block_size;
+        self.explicitly_set.insert("block_size");
+        self
+    }
+
+    /// The gzip level data blocks are compressed with, from 0 (none) to 9 (best).
+    pub fn compression_level(
+        mut self,
+        compression_level: u32,
+    ) -> Self {
+        self.compression_level = compression_level;
+        self.explicitly_set.insert("compression_level");
+        self
+    }
+
+    /// The number of slots of the bloom filter of every SST bundle.
+    pub fn bloom_filter_size(
+        mut self,
+        bloom_filter_size: usize,
+    ) -> Self {
+        self.bloom_filter_size = bloom_filter_size;
+        self.explicitly_set.insert("bloom_filter_size");
+        self
+    }
+
+    /// The number of slots of the bloom filter set for every key.
+    pub fn bloom_filter_hashes(
+        mut self,
+        bloom_filter_hashes: u8,
+    ) -> Self {
+        self.bloom_filter_hashes = bloom_filter_hashes;
+        self.explicitly_set.insert("bloom_filter_hashes");
+        self
+    }
+
+    /// The number of SST bundles in L0 at which they are compacted into L1.
+    pub fn l0_compaction_threshold(
+        mut self,
+        l0_compaction_threshold: usize,
+    ) -> Self {
+        self.l0_compaction_threshold = l0_compaction_threshold;
+        self.explicitly_set.insert("l0_compaction_threshold");
+        self
+    }
+
+    /// The number of SST bundles in L1 at which they are compacted into L2.
+    pub fn l1_compaction_threshold(
+        mut self,
+        l1_compaction_threshold: usize,
+    ) -> Self {
+        self.l1_compaction_threshold = l1_compaction_threshold;
+        self.explicitly_set.insert("l1_compaction_threshold");
+        self
+    }
+
+    /// The number of memtables that may wait for the background flush task.
+    pub fn flush_queue_depth(
+        mut self,
+        flush_queue_depth: usize,
+    ) -> Self {
+        self.flush_queue_depth = flush_queue_depth;
+        self.explicitly_set.insert("flush_queue_depth");
+        self
+    }
+
+    /// The number of column families that may wait for the background compaction task.
+    pub fn compaction_queue_depth(
+        mut self,
+        compaction_queue_depth: usize,
+    ) -> Self {
+        self.compaction_queue_depth = compaction_queue_depth;
+        self.explicitly_set.insert("compaction_queue_depth");
+        self
+    }
+
+    /// The operator combining the operands written with [`DB::merge`](crate::DB::merge).
+    pub fn merge_operator(
+        mut self,
+        merge_operator: Arc<dyn MergeOperator>,
+    ) -> Self {
+        self.merge_operator = Some(merge_operator);
+        self
+    }
+
+    /// Checks that the settings can be used, failing with [`Error::InvalidArgument`] otherwise.
+    pub(crate) fn validate(&self) -> Result<()> {
+        let must_be_positive = [
+            ("max_memtable_size", self.max_memtable_size),
+            ("write_buffer_size", self.write_buffer_size),
+            ("block_size", self.block_size),
+            ("bloom_filter_size", self.bloom_filter_size),
+            ("bloom_filter_hashes", self.bloom_filter_hashes.into()),
+            ("l0_compaction_threshold", self.l0_compaction_threshold),
+            ("l1_compaction_threshold", self.l1_compaction_threshold),
+            ("flush_queue_depth", self.flush_queue_depth),
+            ("compaction_queue_depth", self.compaction_queue_depth),
+        ];
+        for (name, value) in must_be_positive {
+            if value == 0 {
+                return Err(Error::InvalidArgument(format!("{name} must not be 0")));
+            }
+        }
+        if self.compression_level > 9 {
+            return Err(Error::InvalidArgument(format!(
+                "compression_level must be at most 9, not {}",
+                self.compression_level
+            )));
+        }
+        Ok(())
+    }
+
+    /// Completes the settings with the ones the database in `db_path` was opened with before, if
+    /// any, and records the result in its `OPTIONS` file.
+    /// Fails with [`Error::InvalidArgument`] if the database needs a merge operator but has none.
+    pub(crate) async fn persist(
+        mut self,
+        db_path: &Path,
+    ) -> Result<Self> {
+        let path = db_path.join(OPTIONS_FILE_NAME);
+        let mut had_merge_operator = false;
+        if path.exists() {
+            let contents = read_to_string(&path).await?;
+            let previous_options = parse_options_file(&contents)?;
+            self.complete_from(&previous_options)?;
+            had_merge_operator = previous_options.get("merge_operator") == Some(&"true");
+        }
+        if had_merge_operator && self.merge_operator.is_none() {
+            return Err(Error::InvalidArgument(
+                "The database was opened with a merge operator before, it may contain merge \
+                 operands that cannot be read without one"
+                    .to_string(),
+            ));
+        }
+        self.validate()?;
+
+        // Only swapped in once fully written, like the manifest
+        let tmp_path = db_path.join(OPTIONS_TMP_FILE_NAME);
+        let contents = self.to_options_file(had_merge_operator || self.merge_operator.is_some());
+        let mut tmp_file = File::create(&tmp_path).await?;
+        tmp_file.write_all(contents.as_bytes()).await?;
+        tmp_file.sync_all().await?;
+        drop(tmp_file);
+        rename(&tmp_path, &path).await?;
+        sync_dir(db_path).await?;
+        Ok(self)
+    }
+
+    /// Takes the settings that were not set from the parsed `OPTIONS` file.
+    fn complete_from(
+        &mut self,
+        previous_options: &HashMap<&str, &str>,
+    ) -> Result<()> {
+        let previous = PreviousOptions {
+            options: previous_options,
+            explicitly_set: &self.explicitly_set,
+        };
+        previous.complete("max_memtable_size", &mut self.max_memtable_size)?;
+        previous.complete("write_buffer_size", &mut self.write_buffer_size)?;
+        previous.complete("flush_queue_depth", &mut self.flush_queue_depth)?;
+        previous.complete("compaction_queue_depth", &mut self.compaction_queue_depth)?;
+        previous.complete("block_size", &mut self.block_size)?;
+        previous.complete("compression_level", &mut self.compression_level)?;
+        previous.complete("bloom_filter_size", &mut self.bloom_filter_size)?;
+        previous.complete("bloom_filter_hashes", &mut self.bloom_filter_hashes)?;
+        previous.complete("l0_compaction_threshold", &mut self.l0_compaction_threshold)?;
+        previous.complete("l1_compaction_threshold", &mut self.l1_compaction_threshold)
+    }
+
+    /// Formats the settings as `name=value` lines.
+    fn to_options_file(
+        &self,
+        has_merge_operator: bool,
+    ) -> String {
+        let settings: [(&str, &dyn ToString); 11] = [
+            ("max_memtable_size", &self.max_memtable_size),
+            ("write_buffer_size", &self.write_buffer_size),
+            ("block_size", &self.block_size),
+            ("compression_level", &self.compression_level),
+            ("bloom_filter_size", &self.bloom_filter_size),
+            ("bloom_filter_hashes", &self.bloom_filter_hashes),
+            ("l0_compaction_threshold", &self.l0_compaction_threshold),
+            ("l1_compaction_threshold", &self.l1_compaction_threshold),
+            ("flush_queue_depth", &self.flush_queue_depth),
+            ("compaction_queue_depth", &self.compaction_queue_depth),
+            ("merge_operator", &has_merge_operator),
+        ];
+        let mut contents = String::from("# The options the database was last opened with\n");
+        for (name, value) in settings {
+            contents.push_str(&format!("{name}={}\n", value.to_string()));
+        }
+        contents
+    }
+}
+
+/// The settings recorded in the `OPTIONS` file, used to complete the ones of the current open.
+struct PreviousOptions<'a> {
+    options: &'a HashMap<&'a str, &'a str>,
+    explicitly_set: &'a BTreeSet<&'static str>,
+}
+
+impl PreviousOptions<'_> {
+    /// Replaces the value of the setting by the recorded one unless it was set.
+    /// Settings that are not recorded are kept as they are.
+    fn complete<T>(
+        &self,
+        name: &'static str,
+        value: &mut T,
+    ) -> Result<()>
+    where
+        T: FromStr,
+    {
+        let Some(recorded) = self.options.get(name) else {
+            return Ok(());
+        };
+        let recorded = recorded.parse::<T>().map_err(|_| {
+            Error::InvalidData(format!("Invalid value of {name} in OPTIONS: {recorded:?}"))
+        })?;
+        if !self.explicitly_set.contains(name) {
+            *value = recorded;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the `name=value` lines of an `OPTIONS` file, skipping empty lines and comments.
+fn parse_options_file(contents: &str) -> Result<HashMap<&str, &str>> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            line.split_once('=')
+                .ok_or_else(|| Error::InvalidData(format!("Invalid line in OPTIONS: {line:?}")))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_file_lists_all_settings() {
+        let options = Options::default().block_size(1024);
+        let contents = options.to_options_file(true);
+        let parsed = parse_options_file(&contents).unwrap();
+
+        assert_eq!(parsed.len(), 11);
+        assert_eq!(parsed["block_size"], "1024");
+        assert_eq!(parsed["compression_level"], "6");
+        assert_eq!(parsed["merge_operator"], "true");
+        assert!(parse_options_file("block_size 1024").is_err());
+    }
+
+    #[test]
+    fn unset_options_are_taken_from_options_file() {
+        let recorded = Options::default()
+            .block_size(1024)
+            .max_memtable_size(10)
+            .to_options_file(false);
+        let recorded = parse_options_file(&recorded).unwrap();
+
+        let mut options = Options::default().write_buffer_size(4096);
+        options.complete_from(&recorded).unwrap();
+        assert_eq!(options.block_size, 1024);
+        assert_eq!(options.max_memtable_size, 10);
+        assert_eq!(options.write_buffer_size, 4096);
+
+        // Settings that were set replace the recorded ones
+        let mut options = Options::default().max_memtable_size(20).block_size(4096);
+        options.complete_from(&recorded).unwrap();
+        assert_eq!(options.max_memtable_size, 20);
+        assert_eq!(options.block_size, 4096);
+    }
+
+    #[test]
+    fn options_are_validated() {
+        assert!(Options::default().validate().is_ok());
+        assert!(Options::default().block_size(0).validate().is_err());
+        assert!(Options::default().flush_queue_depth(0).validate().is_err());
+        assert!(Options::default().write_buffer_size(0).validate().is_err());
+        assert!(Options::default().compression_level(10).validate().is_err());
+    }
+}
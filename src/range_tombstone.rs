@@ -0,0 +1,181 @@
+use std::io::Cursor;
+use std::ops::Bound;
+use std::ops::RangeBounds;
+
+use bytes::Bytes;
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+
+use crate::checksum::strip_checksum;
+use crate::deserialization::read_entry;
+use crate::error::Error;
+use crate::error::Result;
+use crate::file_handling::SstFileBundle;
+use crate::iterator::is_past_end;
+use crate::iterator::KeyBounds;
+use crate::memtable::MemValue;
+use crate::memtable::SequenceNumber;
+use crate::memtable::Versions;
+use crate::memtable::WriteEntry;
+
+/// Deletes all keys in the range from `start` (inclusive) to `end` (exclusive) that were written
+/// before it.
+///
+/// A range tombstone is stored as a single entry of the memtable or SST bundle holding it, no
+/// matter how many keys it covers.
+#[derive(Debug, Clone)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub(crate) struct RangeTombstone {
+    pub start: Bytes,
+    pub end: Bytes,
+    pub seq: SequenceNumber,
+}
+
+impl RangeTombstone {
+    pub(crate) fn contains(
+        &self,
+        key: &[u8],
+    ) -> bool {
+        self.start.as_ref() <= key && key < self.end.as_ref()
+    }
+
+    /// Whether the tombstone deletes the version of the key with sequence number `seq`.
+    pub(crate) fn covers(
+        &self,
+        key: &[u8],
+        seq: SequenceNumber,
+    ) -> bool {
+        seq < self.seq && self.contains(key)
+    }
+
+    /// Whether the tombstone deletes any key of the range.
+    pub(crate) fn overlaps(
+        &self,
+        range: &KeyBounds,
+    ) -> bool {
+        let starts_before_end = match range.start_bound() {
+            Bound::Included(start) | Bound::Excluded(start) => start < &self.end,
+            Bound::Unbounded => true,
+        };
+        starts_before_end && self.start < self.end && !is_past_end(&self.start, range)
+    }
+}
+
+/// The sequence number of the newest range tombstone visible at the snapshot that contains the
+/// key, if any.
+pub(crate) fn newest_covering<'a>(
+    range_tombstones: impl IntoIterator<Item = &'a RangeTombstone>,
+    key: &[u8],
+    snapshot: SequenceNumber,
+) -> Option<SequenceNumber> {
+    range_tombstones
+        .into_iter()
+        .filter(|tombstone| tombstone.seq <= snapshot && tombstone.contains(key))
+        .map(|tombstone| tombstone.seq)
+        .max()
+}
+
+/// Applies the range tombstones to the versions of the key read at the snapshot, newest first.
+///
+/// Versions older than the newest tombstone containing the key are dropped, and the tombstone takes
+/// their place as a point tombstone unless a newer put or tombstone already shadows them.
+pub(crate) fn apply_range_tombstones(
+    mut versions: Versions,
+    range_tombstones: &[RangeTombstone],
+    key: &[u8],
+    snapshot: SequenceNumber,
+) -> Versions {
+    if let Some(deleted_at) = newest_covering(range_tombstones, key, snapshot) {
+        versions.retain(|value| value.seq() > deleted_at);
+        if versions.iter().all(MemValue::is_merge_operand) {
+            versions.push(MemValue::Delete { seq: deleted_at });
+        }
+    }
+    versions
+}
+
+/// Reads the range tombstones stored in the SST bundle.
+pub(crate) async fn read_range_tombstones(
+    bundle: &SstFileBundle<'_>
+) -> Result<Vec<RangeTombstone>> {
+    let Some(path) = bundle.range_tombstones_file_path else {
+        return Ok(vec![]);
+    };
+    let mut bytes = Vec::new();
+    File::open(path).await?.read_to_end(&mut bytes).await?;
+    strip_checksum(&mut bytes, path)?;
+
+    let mut range_tombstones = Vec::new();
+    let mut cursor = Cursor::new(bytes);
+    while (cursor.position() as usize) < cursor.get_ref().len() {
+        match read_entry(&mut cursor)? {
+            WriteEntry::RangeTombstone(tombstone) => range_tombstones.push(tombstone),
+            WriteEntry::Version(key, _) => {
+                return Err(Error::InvalidData(format!(
+                    "Unexpected version of {key:?} in {path:?}"
+                )));
+            }
+        }
+    }
+    Ok(range_tombstones)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range_tombstone(
+        start: &'static str,
+        end: &'static str,
+        seq: SequenceNumber,
+    ) -> RangeTombstone {
+        RangeTombstone {
+            start: Bytes::from(start),
+            end: Bytes::from(end),
+            seq,
+        }
+    }
+
+    #[test]
+    fn range_tombstones_shadow_older_versions_in_their_range() {
+        let tombstones = [range_tombstone("b", "d", 5), range_tombstone("c", "e", 3)];
+
+        assert_eq!(
+            apply_range_tombstones(vec![MemValue::put(4, "value")], &tombstones, b"c", 10),
+            vec![MemValue::delete(5)]
+        );
+        assert_eq!(
+            apply_range_tombstones(vec![MemValue::put(6, "value")], &tombstones, b"c", 10),
+            vec![MemValue::put(6, "value")]
+        );
+        // The end of the range is exclusive
+        assert_eq!(
+            apply_range_tombstones(vec![MemValue::put(1, "value")], &tombstones, b"d", 10),
+            vec![MemValue::delete(3)]
+        );
+        assert_eq!(
+            apply_range_tombstones(vec![MemValue::put(1, "value")], &tombstones, b"e", 10),
+            vec![MemValue::put(1, "value")]
+        );
+        // Tombstones newer than the snapshot are ignored
+        assert_eq!(
+            apply_range_tombstones(vec![MemValue::put(1, "value")], &tombstones, b"b", 4),
+            vec![MemValue::put(1, "value")]
+        );
+        // The key may only be stored in an older memtable or bundle
+        assert_eq!(
+            apply_range_tombstones(vec![], &tombstones, b"b", 10),
+            vec![MemValue::delete(5)]
+        );
+        let merge = MemValue::merge(7, "operand");
+        assert_eq!(
+            apply_range_tombstones(
+                vec![merge.clone(), MemValue::put(2, "value")],
+                &tombstones,
+                b"b",
+                10
+            ),
+            vec![merge, MemValue::delete(5)]
+        );
+    }
+}
@@ -1,24 +1,42 @@
+use std::io;
 use std::io::Write;
 use std::mem;
 
-use anyhow::Result;
 use flate2::write::GzEncoder;
 use flate2::Compression;
 
 use crate::bloom_filter::BloomFilter;
 use crate::bloom_filter::DefaultBloomFilter;
+use crate::checksum::append_checksum;
+use crate::checksum::checksum;
+use crate::error::Result;
+use crate::file_handling::KeyRange;
 use crate::memtable::MemTable;
+use crate::memtable::MemTableGet;
 use crate::memtable::MemValue;
+use crate::memtable::SequenceNumber;
+use crate::memtable::Timestamp;
+use crate::memtable::WriteEntry;
+use crate::options::Options;
+use crate::range_tombstone::RangeTombstone;
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub(crate) struct SerializedTableData {
     pub main_data: Vec<u8>,
     pub offsets: Vec<u8>,
     pub bloom_filter: DefaultBloomFilter,
+    pub key_range: KeyRange,
+    pub largest_seq: SequenceNumber,
+    // The checksummed range tombstones, if there are any
+    pub range_tombstones: Option<Vec<u8>>,
 }
 
 pub(crate) trait Serialize {
-    fn serialize(self) -> Result<SerializedTableData>;
+    /// Serializes the data into blocks and a bloom filter as configured by the options.
+    fn serialize(
+        self,
+        options: &Options,
+    ) -> Result<SerializedTableData>;
 }
 
 #[derive(Debug)]
@@ -26,76 +44,237 @@ struct SerializedFoldState {
     table_data: SerializedTableData,
     encoder: GzEncoder<Vec<u8>>,
     encoded_bytes: usize,
-    offset_counter: usize,
 }
 
 impl SerializedFoldState {
-    fn new() -> Self {
+    fn new(options: &Options) -> Self {
         Self {
-            table_data: Default::default(),
-            encoder: GzEncoder::new(Vec::new(), Compression::default()),
+            table_data: SerializedTableData {
+                main_data: Default::default(),
+                offsets: Default::default(),
+                bloom_filter: DefaultBloomFilter::new(
+                    options.bloom_filter_size,
+                    options.bloom_filter_hashes,
+                ),
+                key_range: Default::default(),
+                largest_seq: Default::default(),
+                range_tombstones: None,
+            },
+            encoder: new_encoder(options),
             encoded_bytes: 0,
-            offset_counter: 0,
         }
     }
 }
 
+fn new_encoder(options: &Options) -> GzEncoder<Vec<u8>> {
+    GzEncoder::new(Vec::new(), Compression::new(options.compression_level))
+}
+
 impl Serialize for MemTable {
-    fn serialize(self) -> Result<SerializedTableData> {
-        let n_elements = self.len();
-        let fold_result = self.into_iter().enumerate().try_fold(
-            SerializedFoldState::new(),
-            |mut state, (idx, (key, value))| -> Result<_> {
-                // Encode the key length and value length first for easier parsing
-                let key_len = key.len() as u64;
-                let key_len_bytes = key_len.to_be_bytes();
-                let key_bytes = key.as_bytes();
-                state.table_data.bloom_filter.add_key(&key);
-
-                if state.encoded_bytes == 0 {
-                    state.table_data.offsets.extend(&key_len_bytes);
-                    state.table_data.offsets.extend(key_bytes);
+    fn serialize(
+        self,
+        options: &Options,
+    ) -> Result<SerializedTableData> {
+        let mut state = SerializedFoldState::new(options);
+        let range_tombstones = self.range_tombstones().to_vec();
+        let mut entries = self.into_entries().enumerate().peekable();
+        while let Some((idx, (key, value))) = entries.next() {
+            state.table_data.bloom_filter.add_key(&key);
+            if idx == 0 {
+                state.table_data.key_range.smallest = key.clone();
+            }
+            state.table_data.largest_seq = state.table_data.largest_seq.max(value.seq());
+
+            if state.encoded_bytes == 0 {
+                state
+                    .table_data
+                    .offsets
+                    .extend((key.len() as u64).to_be_bytes());
+                state.table_data.offsets.extend(&key);
+            }
+
+            state.encoded_bytes += write_key_value(&mut state.encoder, &key, &value)?;
+
+            // Encode data above threshold or when it's the last element.
+            // All versions of a key are kept in the same block so that a lookup only needs to
+            // read the single block its key may be stored in.
+            let is_block_full = match entries.peek() {
+                Some((_, (next_key, _))) => {
+                    state.encoded_bytes >= options.block_size && *next_key != key
+                }
+                None => {
+                    state.table_data.key_range.largest = key.clone();
+                    true
                 }
+            };
+            if is_block_full {
+                let mut encoder = new_encoder(options);
+                mem::swap(&mut state.encoder, &mut encoder);
+                state.encoded_bytes = 0;
 
-                state.encoded_bytes += state.encoder.write(&key_len_bytes)?;
-                state.encoded_bytes += state.encoder.write(key_bytes)?;
-
-                match value {
-                    MemValue::Delete => {
-                        state.encoded_bytes += state.encoder.write(&[0])?;
-                    }
-                    MemValue::Put(value_str) => {
-                        state.encoded_bytes += state.encoder.write(&[1])?;
-                        state.encoded_bytes +=
-                            state.encoder.write(&value_str.len().to_be_bytes())?;
-                        state.encoded_bytes += state.encoder.write(value_str.as_bytes())?;
-                    }
-                };
-
-                // Encode data above threshold or when it's the last element
-                if state.encoded_bytes >= 4096 || idx == n_elements - 1 {
-                    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-                    mem::swap(&mut state.encoder, &mut encoder);
-                    state.encoded_bytes = 0;
-
-                    let encoded_data = encoder.finish()?;
-                    let encoded_len = encoded_data.len();
-                    // Save next encoded block length first so that the file can be read as is
-                    state
-                        .table_data
-                        .main_data
-                        .extend((encoded_len as u64).to_be_bytes());
-                    // Store encoded block
-                    state.table_data.main_data.extend(encoded_data);
-                    state
-                        .table_data
-                        .offsets
-                        .extend((state.offset_counter as u64).to_be_bytes());
-                    state.offset_counter += encoded_len
+                let encoded_data = encoder.finish()?;
+                let block_offset = state.table_data.main_data.len() as u64;
+                // Save next encoded block length and its checksum first so that the file can
+                // be read as is
+                state
+                    .table_data
+                    .main_data
+                    .extend((encoded_data.len() as u64).to_be_bytes());
+                state
+                    .table_data
+                    .main_data
+                    .extend(checksum(&encoded_data).to_be_bytes());
+                // Store encoded block
+                state.table_data.main_data.extend(encoded_data);
+                state.table_data.offsets.extend(block_offset.to_be_bytes());
+            }
+        }
+
+        if !range_tombstones.is_empty() {
+            let table_data = &mut state.table_data;
+            let mut range_tombstones_bytes = Vec::new();
+            for (idx, tombstone) in range_tombstones.iter().enumerate() {
+                // The key range covers the range tombstones so that reads do not skip the bundle
+                if idx == 0 && table_data.main_data.is_empty() {
+                    table_data.key_range = KeyRange {
+                        smallest: tombstone.start.clone(),
+                        largest: tombstone.end.clone(),
+                    };
+                } else {
+                    let key_range = &mut table_data.key_range;
+                    key_range.smallest = key_range.smallest.clone().min(tombstone.start.clone());
+                    key_range.largest = key_range.largest.clone().max(tombstone.end.clone());
                 }
-                Ok(state)
-            },
-        )?;
-        Ok(fold_result.table_data)
+                table_data.largest_seq = table_data.largest_seq.max(tombstone.seq);
+                write_range_tombstone(&mut range_tombstones_bytes, tombstone)?;
+            }
+            append_checksum(&mut range_tombstones_bytes);
+            table_data.range_tombstones = Some(range_tombstones_bytes);
+        }
+        Ok(state.table_data)
+    }
+}
+
+/// Writes a single write of a batch to the writer.
+/// Returns the number of bytes written.
+pub(crate) fn write_entry<W: Write>(
+    writer: &mut W,
+    entry: &WriteEntry,
+) -> io::Result<usize> {
+    match entry {
+        WriteEntry::Version(key, value) => write_key_value(writer, key, value),
+        WriteEntry::RangeTombstone(tombstone) => write_range_tombstone(writer, tombstone),
+    }
+}
+
+/// Writes a range tombstone to the writer, keyed by the start of its range.
+/// Returns the number of bytes written.
+pub(crate) fn write_range_tombstone<W: Write>(
+    writer: &mut W,
+    tombstone: &RangeTombstone,
+) -> io::Result<usize> {
+    // Range tombstone is encoded as 4, followed by the end of its range
+    write_with_value(writer, &tombstone.start, tombstone.seq, 4, &tombstone.end)
+}
+
+/// Writes a key with its value, which may be a tombstone, to the writer.
+/// Returns the number of bytes written.
+pub(crate) fn write_key_value<W: Write>(
+    writer: &mut W,
+    key: &[u8],
+    value: &MemValue,
+) -> io::Result<usize> {
+    match value {
+        MemValue::Put {
+            seq,
+            value,
+            expires_at: None,
+        } => write_put(writer, key, *seq, value),
+        MemValue::Put {
+            seq,
+            value,
+            expires_at: Some(expires_at),
+        } => write_put_with_expiry(writer, key, *seq, value, *expires_at),
+        MemValue::Delete { seq } => write_delete(writer, key, *seq),
+        // Merge is encoded as 2
+        MemValue::Merge { seq, operand } => write_with_value(writer, key, *seq, 2, operand),
     }
 }
+
+fn write_put<W: Write>(
+    writer: &mut W,
+    key: &[u8],
+    seq: SequenceNumber,
+    value: &[u8],
+) -> io::Result<usize> {
+    // Put is encoded as 1
+    write_with_value(writer, key, seq, 1, value)
+}
+
+fn write_put_with_expiry<W: Write>(
+    writer: &mut W,
+    key: &[u8],
+    seq: SequenceNumber,
+    value: &[u8],
+    expires_at: Timestamp,
+) -> io::Result<usize> {
+    let mut written = write_key(writer, key, seq)?;
+    // Put with an expiry is encoded as 3, followed by the expiry timestamp
+    writer.write_all(&[3])?;
+    writer.write_all(&expires_at.to_be_bytes())?;
+    writer.write_all(&(value.len() as u64).to_be_bytes())?;
+    writer.write_all(value)?;
+    written += 1 + 8 + 8 + value.len();
+    Ok(written)
+}
+
+/// Writes a record of a type that carries a value, i.e. a put, a merge operand or a range
+/// tombstone.
+fn write_with_value<W: Write>(
+    writer: &mut W,
+    key: &[u8],
+    seq: SequenceNumber,
+    value_type: u8,
+    value: &[u8],
+) -> io::Result<usize> {
+    let mut written = write_key(writer, key, seq)?;
+    writer.write_all(&[value_type])?;
+    writer.write_all(&(value.len() as u64).to_be_bytes())?;
+    writer.write_all(value)?;
+    written += 1 + 8 + value.len();
+    Ok(written)
+}
+
+fn write_delete<W: Write>(
+    writer: &mut W,
+    key: &[u8],
+    seq: SequenceNumber,
+) -> io::Result<usize> {
+    let written = write_key(writer, key, seq)?;
+    // Delete is encoded as 0
+    writer.write_all(&[0])?;
+    Ok(written + 1)
+}
+
+fn write_key<W: Write>(
+    writer: &mut W,
+    key: &[u8],
+    seq: SequenceNumber,
+) -> io::Result<usize> {
+    // Encode the key length first for easier parsing
+    writer.write_all(&(key.len() as u64).to_be_bytes())?;
+    writer.write_all(key)?;
+    writer.write_all(&seq.to_be_bytes())?;
+    Ok(8 + key.len() + 8)
+}
+
+/// Appends the payload to the buffer, framed with its length and checksum so that a torn write at
+/// the end of an append-only log can be detected.
+pub(crate) fn write_record(
+    buffer: &mut Vec<u8>,
+    payload: &[u8],
+) {
+    buffer.extend((payload.len() as u64).to_be_bytes());
+    buffer.extend(checksum(payload).to_be_bytes());
+    buffer.extend(payload);
+}
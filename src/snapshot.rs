@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use crate::memtable::SequenceNumber;
+
+/// Keeps track of the snapshots that are still alive.
+/// Older versions of a key must be kept as long as a live snapshot may read them.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SnapshotList(Arc<Mutex<BTreeMap<SequenceNumber, usize>>>);
+
+impl SnapshotList {
+    /// Takes a snapshot of the database as of the write with sequence number `seq`.
+    pub(crate) fn acquire(
+        &self,
+        seq: SequenceNumber,
+    ) -> Snapshot {
+        // Unwrap is OK as the lock is never held while panicking
+        *self.0.lock().unwrap().entry(seq).or_default() += 1;
+        Snapshot {
+            seq,
+            list: self.clone(),
+        }
+    }
+
+    /// The sequence numbers of all live snapshots.
+    pub(crate) fn live(&self) -> BTreeSet<SequenceNumber> {
+        self.0.lock().unwrap().keys().copied().collect()
+    }
+
+    fn release(
+        &self,
+        seq: SequenceNumber,
+    ) {
+        let mut snapshots = self.0.lock().unwrap();
+        if let Some(count) = snapshots.get_mut(&seq) {
+            *count -= 1;
+            if *count == 0 {
+                snapshots.remove(&seq);
+            }
+        }
+    }
+}
+
+/// A consistent point-in-time view of the database.
+///
+/// Reads with a snapshot only see the writes made before the snapshot was taken.
+/// The versions it reads are retained until the snapshot is dropped.
+#[derive(Debug)]
+pub struct Snapshot {
+    seq: SequenceNumber,
+    list: SnapshotList,
+}
+
+impl Snapshot {
+    /// The sequence number of the last write visible to the snapshot.
+    pub fn sequence_number(&self) -> u64 {
+        self.seq
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.list.release(self.seq);
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+
+use bytes::Bytes;
+
+use crate::db::BaumDb;
+use crate::db::DB;
+use crate::error::Result;
+use crate::error::TransactionConflictError;
+use crate::snapshot::Snapshot;
+use crate::write_batch::WriteBatch;
+
+/// An optimistic transaction.
+///
+/// Reads see the state of the database as of the start of the transaction, plus the transaction's
+/// own writes. Writes are buffered until the transaction is committed.
+/// No locks are taken, instead committing fails with a [`TransactionConflictError`] if any key
+/// read by the transaction was modified after it began.
+#[derive(Debug)]
+pub struct Transaction {
+    snapshot: Snapshot,
+    read_keys: HashSet<Bytes>,
+    // The buffered writes, `None` being a delete
+    writes: BTreeMap<Bytes, Option<Bytes>>,
+}
+
+impl Transaction {
+    pub(crate) fn new(snapshot: Snapshot) -> Self {
+        Self {
+            snapshot,
+            read_keys: Default::default(),
+            writes: Default::default(),
+        }
+    }
+
+    /// Gets the value of the key, tracking the key for conflict detection.
+    pub async fn get<K>(
+        &mut self,
+        db: &BaumDb,
+        key: K,
+    ) -> Result<Option<Bytes>>
+    where
+        K: AsRef<[u8]> + Send + Sync,
+    {
+        let key = key.as_ref();
+        if let Some(value) = self.writes.get(key) {
+            return Ok(value.clone());
+        }
+        self.read_keys.insert(Bytes::copy_from_slice(key));
+        db.get_with_snapshot(key, &self.snapshot).await
+    }
+
+    /// Buffers a put of `value` for `key`.
+    pub fn put<K, V>(
+        &mut self,
+        key: K,
+        value: V,
+    ) where
+        K: Into<Bytes>,
+        V: Into<Bytes>,
+    {
+        self.writes.insert(key.into(), Some(value.into()));
+    }
+
+    /// Buffers a delete of `key`.
+    pub fn delete<K>(
+        &mut self,
+        key: K,
+    ) where
+        K: AsRef<[u8]>,
+    {
+        self.writes
+            .insert(Bytes::copy_from_slice(key.as_ref()), None);
+    }
+
+    /// Atomically applies the buffered writes to the database.
+    ///
+    /// Fails with a [`TransactionConflictError`] without applying any write if a key read by the
+    /// transaction was modified after it began.
+    pub async fn commit(
+        self,
+        db: &BaumDb,
+    ) -> Result<()> {
+        // Held until the batch is applied, so that no write can slip in after the check
+        let writer = db.lock_writes().await;
+        for key in self.read_keys.iter() {
+            let latest_seq = db.latest_seq(key).await?;
+            if latest_seq.is_some_and(|seq| seq > self.snapshot.sequence_number()) {
+                return Err(TransactionConflictError::new(key.clone()).into());
+            }
+        }
+        let mut batch = WriteBatch::new();
+        for (key, value) in self.writes {
+            match value {
+                Some(value) => batch.put(key, value),
+                None => batch.delete(key),
+            }
+        }
+        db.write_locked(&writer, batch).await
+    }
+}
@@ -0,0 +1,453 @@
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use tokio::fs::read_dir;
+use tokio::fs::remove_file;
+use tokio::fs::File;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+
+use crate::deserialization::read_entry;
+use crate::deserialization::read_records;
+use crate::error::CorruptionError;
+use crate::error::Result;
+use crate::file_handling::sync_dir;
+use crate::memtable::MemTable;
+use crate::memtable::MemTableGet;
+use crate::memtable::MemTableWrite;
+use crate::memtable::WriteEntry;
+use crate::serialization::write_entry;
+use crate::serialization::write_key_value;
+use crate::serialization::write_range_tombstone;
+use crate::serialization::write_record;
+
+const WAL_FILE_PREFIX: &str = "wal-";
+const WAL_FILE_SUFFIX: &str = ".log";
+
+/// The write-ahead log backing the main memtable.
+///
+/// Every write is appended to the log and synced to disk before it is applied to the memtable so
+/// that it can be replayed after a crash. Each memtable gets its own log file, which is rotated
+/// when the memtable is flushed and removed once the memtable's data has been committed to an SST
+/// bundle.
+#[derive(Debug)]
+pub(crate) struct Wal {
+    dir_path: PathBuf,
+    number: u64,
+    file: File,
+    // The length of the log file up to the end of its last complete record
+    len: u64,
+    // Whether a failed write may have left a torn record after `len`
+    is_torn: bool,
+}
+
+impl Wal {
+    /// Opens the write-ahead log in `dir_path`.
+    /// Any log files left over from a previous run are replayed into the returned memtable.
+    pub(crate) async fn open<P>(dir_path: P) -> Result<(Self, MemTable)>
+    where
+        P: Into<PathBuf>,
+    {
+        let dir_path = dir_path.into();
+        let existing_log_files = existing_log_files(&dir_path).await?;
+        let mut memtable = MemTable::default();
+        for (idx, (_, path)) in existing_log_files.iter().enumerate() {
+            let Some(torn_offset) = replay(path, &mut memtable).await? else {
+                continue;
+            };
+            // Only the last write before a crash may be torn, which was never acknowledged. Any
+            // torn record before it hides acknowledged writes.
+            if idx + 1 < existing_log_files.len() {
+                return Err(CorruptionError::new(path, torn_offset).into());
+            }
+            // Should we crash before the log is removed below, it is no longer the newest one
+            let file = OpenOptions::new().write(true).open(path).await?;
+            file.set_len(torn_offset).await?;
+            file.sync_all().await?;
+        }
+
+        let number = existing_log_files
+            .last()
+            .map(|(number, _)| number + 1)
+            .unwrap_or_default();
+        let file = create_log_file(&log_file_path(&dir_path, number)).await?;
+        let mut wal = Self {
+            dir_path,
+            number,
+            file,
+            len: 0,
+            is_torn: false,
+        };
+
+        // Consolidate the replayed entries into the fresh log so that the old ones can be dropped.
+        // Should we crash before removing them, their entries are replayed twice, which the
+        // memtable ignores as it already holds versions with the same sequence numbers.
+        let mut records = Vec::new();
+        for (key, value) in memtable.iter() {
+            encode_record(&mut records, |payload| write_key_value(payload, key, value))?;
+        }
+        for tombstone in memtable.range_tombstones() {
+            encode_record(&mut records, |payload| {
+                write_range_tombstone(payload, tombstone)
+            })?;
+        }
+        wal.write(&records).await?;
+        wal.file.sync_all().await?;
+        for (_, path) in existing_log_files {
+            remove_file(path).await?;
+        }
+        sync_dir(&wal.dir_path).await?;
+
+        Ok((wal, memtable))
+    }
+
+    /// Appends the writes of a batch to the log as a single record.
+    /// As a torn record is ignored on replay, either all or none of the writes are recovered.
+    pub(crate) async fn append_batch(
+        &mut self,
+        entries: &[WriteEntry],
+    ) -> Result<()> {
+        let mut record = Vec::new();
+        encode_record(&mut record, |payload| {
+            entries.iter().try_fold(0, |written, entry| {
+                Ok(written + write_entry(payload, entry)?)
+            })
+        })?;
+        self.write(&record).await
+    }
+
+    /// Starts a new log file for the next memtable.
+    /// Returns the path of the previous log file which can be removed once its memtable is
+    /// flushed.
+    pub(crate) async fn rotate(&mut self) -> Result<PathBuf> {
+        // Only the newest log may end in a torn record
+        self.truncate_torn_record().await?;
+        let previous_path = log_file_path(&self.dir_path, self.number);
+        let next_number = self.number + 1;
+        self.file = create_log_file(&log_file_path(&self.dir_path, next_number)).await?;
+        // The writes logged to the new file would be lost along with it otherwise
+        sync_dir(&self.dir_path).await?;
+        self.number = next_number;
+        self.len = 0;
+        Ok(previous_path)
+    }
+
+    async fn write(
+        &mut self,
+        records: &[u8],
+    ) -> Result<()> {
+        self.truncate_torn_record().await?;
+        let result = self.append(records).await;
+        if result.is_err() {
+            // Should this fail as well, it is retried before the next write
+            let _ = self.truncate_torn_record().await;
+        }
+        result
+    }
+
+    async fn append(
+        &mut self,
+        records: &[u8],
+    ) -> Result<()> {
+        // Set until the records are on disk, which they may partially be if writing them fails
+        self.is_torn = true;
+        self.file.write_all(records).await?;
+        // Make sure the data is on disk before the write is acknowledged, so that it survives a
+        // crash of the OS as well
+        self.file.flush().await?;
+        self.file.sync_data().await?;
+        self.is_torn = false;
+        self.len += records.len() as u64;
+        Ok(())
+    }
+
+    /// Cuts off what a failed write left behind after the last complete record.
+    /// Replay stops at a torn record, so any write logged after it would be lost otherwise.
+    async fn truncate_torn_record(&mut self) -> Result<()> {
+        if self.is_torn {
+            self.file.set_len(self.len).await?;
+            self.file.sync_data().await?;
+            self.is_torn = false;
+        }
+        Ok(())
+    }
+}
+
+/// Frames the payload written by `write_payload` as a single record of the log.
+fn encode_record<F>(
+    buffer: &mut Vec<u8>,
+    write_payload: F,
+) -> Result<()>
+where
+    F: FnOnce(&mut Vec<u8>) -> io::Result<usize>,
+{
+    let mut payload = Vec::new();
+    write_payload(&mut payload)?;
+    write_record(buffer, &payload);
+    Ok(())
+}
+
+/// Replays the log file into the memtable.
+/// Returns the offset of the torn record the log ends in, if any.
+async fn replay(
+    path: &Path,
+    memtable: &mut MemTable,
+) -> Result<Option<u64>> {
+    let mut log_bytes = Vec::new();
+    File::open(path).await?.read_to_end(&mut log_bytes).await?;
+
+    let mut records = read_records(&log_bytes, path);
+    for record in records.by_ref() {
+        let mut record = record?;
+        // A record holds all writes of a batch
+        while !record.is_empty() {
+            memtable.apply(read_entry(&mut record)?);
+        }
+    }
+    Ok(records.torn_offset())
+}
+
+/// Returns the log files in `dir_path` ordered from oldest to newest.
+async fn existing_log_files(dir_path: &Path) -> Result<Vec<(u64, PathBuf)>> {
+    let mut log_files = Vec::new();
+    let mut entries = read_dir(dir_path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let file_name = entry.file_name();
+        let Some(number) = file_name
+            .to_str()
+            .and_then(|name| name.strip_prefix(WAL_FILE_PREFIX))
+            .and_then(|name| name.strip_suffix(WAL_FILE_SUFFIX))
+            .and_then(|number| number.parse::<u64>().ok())
+        else {
+            continue;
+        };
+        log_files.push((number, entry.path()));
+    }
+    log_files.sort_unstable_by_key(|(number, _)| *number);
+    Ok(log_files)
+}
+
+fn log_file_path(
+    dir_path: &Path,
+    number: u64,
+) -> PathBuf {
+    dir_path.join(format!("{WAL_FILE_PREFIX}{number}{WAL_FILE_SUFFIX}"))
+}
+
+async fn create_log_file(path: &Path) -> Result<File> {
+    let file = OpenOptions::new()
+        .append(true)
+        .create_new(true)
+        .open(path)
+        .await?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use tokio::fs::create_dir_all;
+    use tokio::fs::remove_dir_all;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::error::Error;
+    use crate::memtable::MemValue;
+    use crate::range_tombstone::RangeTombstone;
+
+    fn version(
+        key: &'static str,
+        value: MemValue,
+    ) -> WriteEntry {
+        WriteEntry::Version(Bytes::from(key), value)
+    }
+
+    /// Cuts off the last `n_bytes` of the log file.
+    async fn truncate_log_end(
+        wal: &mut Wal,
+        n_bytes: u64,
+    ) {
+        let len = wal.file.metadata().await.unwrap().len();
+        wal.file.set_len(len - n_bytes).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn replay_ignores_torn_write_at_end_of_log() {
+        let path = PathBuf::from(format!("./test-logs/{:?}", Uuid::new_v4()));
+        create_dir_all(&path).await.unwrap();
+
+        let (mut wal, memtable) = Wal::open(&path).await.unwrap();
+        assert_eq!(memtable.len(), 0);
+        wal.append_batch(&[version("foo", MemValue::put(1, "bar"))])
+            .await
+            .unwrap();
+        wal.append_batch(&[version("baz", MemValue::put(2, "qux"))])
+            .await
+            .unwrap();
+        wal.append_batch(&[version("baz", MemValue::delete(3))])
+            .await
+            .unwrap();
+        wal.append_batch(&[WriteEntry::RangeTombstone(RangeTombstone {
+            start: Bytes::from("f"),
+            end: Bytes::from("g"),
+            seq: 4,
+        })])
+        .await
+        .unwrap();
+        // Simulate a crash in the middle of writing a record
+        wal.append_batch(&[
+            version("torn", MemValue::put(5, "value")),
+            version("other", MemValue::put(6, "value")),
+        ])
+        .await
+        .unwrap();
+        truncate_log_end(&mut wal, 3).await;
+        wal.file.flush().await.unwrap();
+        drop(wal);
+
+        let (_wal, memtable) = Wal::open(&path).await.unwrap();
+        assert_eq!(
+            memtable.get(b"foo", 3).unwrap(),
+            vec![MemValue::put(1, "bar")]
+        );
+        assert_eq!(
+            memtable.get(b"foo", u64::MAX).unwrap(),
+            vec![MemValue::delete(4)]
+        );
+        assert_eq!(
+            memtable.get(b"baz", u64::MAX).unwrap(),
+            vec![MemValue::delete(3)]
+        );
+        // None of the writes of the torn batch are recovered
+        assert!(memtable.get(b"torn", u64::MAX).unwrap().is_empty());
+        assert!(memtable.get(b"other", u64::MAX).unwrap().is_empty());
+        assert_eq!(existing_log_files(&path).await.unwrap().len(), 1);
+
+        let _ = remove_dir_all(path).await;
+    }
+
+    #[tokio::test]
+    async fn writes_after_a_failed_write_are_replayed() {
+        let path = PathBuf::from(format!("./test-logs/{:?}", Uuid::new_v4()));
+        create_dir_all(&path).await.unwrap();
+
+        let (mut wal, _) = Wal::open(&path).await.unwrap();
+        wal.append_batch(&[version("foo", MemValue::put(1, "bar"))])
+            .await
+            .unwrap();
+        // A write that failed after part of its record reached the file
+        wal.is_torn = true;
+        wal.file.write_all(&[0xff; 5]).await.unwrap();
+        wal.file.flush().await.unwrap();
+        wal.append_batch(&[version("after", MemValue::put(2, "value"))])
+            .await
+            .unwrap();
+        drop(wal);
+
+        let (_wal, memtable) = Wal::open(&path).await.unwrap();
+        assert_eq!(
+            memtable.get(b"foo", 2).unwrap(),
+            vec![MemValue::put(1, "bar")]
+        );
+        assert_eq!(
+            memtable.get(b"after", 2).unwrap(),
+            vec![MemValue::put(2, "value")]
+        );
+
+        let _ = remove_dir_all(path).await;
+    }
+
+    #[tokio::test]
+    async fn replaying_the_same_log_twice_keeps_each_version_once() {
+        let path = PathBuf::from(format!("./test-logs/{:?}", Uuid::new_v4()));
+        create_dir_all(&path).await.unwrap();
+
+        let (mut wal, _) = Wal::open(&path).await.unwrap();
+        wal.append_batch(&[version("counter", MemValue::put(1, "1"))])
+            .await
+            .unwrap();
+        let operand = MemValue::merge(2, "1");
+        wal.append_batch(&[version("counter", operand.clone())])
+            .await
+            .unwrap();
+        wal.append_batch(&[WriteEntry::RangeTombstone(RangeTombstone {
+            start: Bytes::from("a"),
+            end: Bytes::from("b"),
+            seq: 3,
+        })])
+        .await
+        .unwrap();
+        drop(wal);
+        // As left behind by a crash before the replayed logs were removed
+        tokio::fs::copy(log_file_path(&path, 0), log_file_path(&path, 100))
+            .await
+            .unwrap();
+
+        let (_wal, memtable) = Wal::open(&path).await.unwrap();
+        assert_eq!(
+            memtable.get(b"counter", u64::MAX).unwrap(),
+            vec![operand, MemValue::put(1, "1")]
+        );
+        assert_eq!(memtable.len(), 3);
+
+        let _ = remove_dir_all(path).await;
+    }
+
+    #[tokio::test]
+    async fn corrupted_records_before_the_end_of_the_newest_log_are_reported() {
+        let path = PathBuf::from(format!("./test-logs/{:?}", Uuid::new_v4()));
+        create_dir_all(&path).await.unwrap();
+
+        let (mut wal, _) = Wal::open(&path).await.unwrap();
+        wal.append_batch(&[version("foo", MemValue::put(1, "bar"))])
+            .await
+            .unwrap();
+        let corrupted_offset = wal.len;
+        // A record whose payload does not match its checksum
+        let garbage = [0xff; 32];
+        let mut record = Vec::new();
+        record.extend((garbage.len() as u64).to_be_bytes());
+        record.extend(0u32.to_be_bytes());
+        record.extend(garbage);
+        wal.write(&record).await.unwrap();
+        wal.append_batch(&[version("after", MemValue::put(2, "value"))])
+            .await
+            .unwrap();
+        drop(wal);
+
+        let Err(Error::Corruption(error)) = Wal::open(&path).await else {
+            panic!("The corrupted record was not reported");
+        };
+        assert_eq!(error.path(), log_file_path(&path, 0));
+        assert_eq!(error.offset(), corrupted_offset);
+        // The logs are kept, so that the writes can still be recovered by hand
+        assert_eq!(existing_log_files(&path).await.unwrap().len(), 1);
+
+        // A torn record is reported as well once another log follows it
+        remove_file(log_file_path(&path, 0)).await.unwrap();
+        let (mut wal, _) = Wal::open(&path).await.unwrap();
+        wal.append_batch(&[version("foo", MemValue::put(1, "bar"))])
+            .await
+            .unwrap();
+        let torn_offset = wal.len;
+        wal.append_batch(&[version("torn", MemValue::put(2, "value"))])
+            .await
+            .unwrap();
+        truncate_log_end(&mut wal, 3).await;
+        wal.rotate().await.unwrap();
+        wal.append_batch(&[version("after", MemValue::put(3, "value"))])
+            .await
+            .unwrap();
+        drop(wal);
+
+        let Err(Error::Corruption(error)) = Wal::open(&path).await else {
+            panic!("The torn record was not reported");
+        };
+        assert_eq!(error.path(), log_file_path(&path, 0));
+        assert_eq!(error.offset(), torn_offset);
+
+        let _ = remove_dir_all(path).await;
+    }
+}
@@ -0,0 +1,158 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use crate::memtable::current_timestamp;
+use crate::memtable::MemValue;
+use crate::memtable::SequenceNumber;
+use crate::memtable::WriteEntry;
+use crate::range_tombstone::RangeTombstone;
+
+#[derive(Debug, Clone)]
+enum Operation {
+    Put(Bytes, Option<Duration>),
+    Delete,
+    Merge(Bytes),
+    // Deletes the keys from the entry's key up to this one, exclusive
+    DeleteRange(Bytes),
+}
+
+/// A set of puts, deletes, range deletes and merges that is applied to the database atomically.
+///
+/// Either all or none of the writes become visible, even in case of a crash. The writes are applied
+/// in the order they were added, so a later write to a key overrides an earlier one.
+#[derive(Debug, Default, Clone)]
+pub struct WriteBatch {
+    entries: Vec<(Bytes, Operation)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a put of `value` for `key` to the batch.
+    pub fn put<K, V>(
+        &mut self,
+        key: K,
+        value: V,
+    ) where
+        K: Into<Bytes>,
+        V: Into<Bytes>,
+    {
+        self.entries
+            .push((key.into(), Operation::Put(value.into(), None)));
+    }
+
+    /// Adds a put of `value` for `key` to the batch that expires `ttl` after the batch is written.
+    /// The key reads as absent once expired.
+    pub fn put_with_ttl<K, V>(
+        &mut self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) where
+        K: Into<Bytes>,
+        V: Into<Bytes>,
+    {
+        self.entries
+            .push((key.into(), Operation::Put(value.into(), Some(ttl))));
+    }
+
+    /// Adds a delete of `key` to the batch.
+    pub fn delete<K>(
+        &mut self,
+        key: K,
+    ) where
+        K: AsRef<[u8]>,
+    {
+        self.entries
+            .push((Bytes::copy_from_slice(key.as_ref()), Operation::Delete));
+    }
+
+    /// Adds a delete of all keys from `start` (inclusive) to `end` (exclusive) to the batch.
+    /// It is written as a single range tombstone, no matter how many keys it deletes.
+    /// Writing the batch fails if `start` is not smaller than `end`.
+    pub fn delete_range<K>(
+        &mut self,
+        start: K,
+        end: K,
+    ) where
+        K: AsRef<[u8]>,
+    {
+        self.entries.push((
+            Bytes::copy_from_slice(start.as_ref()),
+            Operation::DeleteRange(Bytes::copy_from_slice(end.as_ref())),
+        ));
+    }
+
+    /// Adds a merge of `operand` into the value of `key` to the batch.
+    pub fn merge<K, V>(
+        &mut self,
+        key: K,
+        operand: V,
+    ) where
+        K: Into<Bytes>,
+        V: Into<Bytes>,
+    {
+        self.entries
+            .push((key.into(), Operation::Merge(operand.into())));
+    }
+
+    /// The number of writes in the batch.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether the batch contains a merge, which can only be applied with a merge operator.
+    pub(crate) fn has_merges(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|(_, operation)| matches!(operation, Operation::Merge(_)))
+    }
+
+    /// Whether the batch contains a range delete whose start is not smaller than its end, which
+    /// would not delete any key.
+    pub(crate) fn has_empty_ranges(&self) -> bool {
+        self.entries.iter().any(
+            |(start, operation)| matches!(operation, Operation::DeleteRange(end) if start >= end),
+        )
+    }
+
+    /// Tags the writes with consecutive sequence numbers starting at `first_seq`, in the order
+    /// they were added.
+    pub(crate) fn into_entries(
+        self,
+        first_seq: SequenceNumber,
+    ) -> Vec<WriteEntry> {
+        let now = current_timestamp();
+        (first_seq..)
+            .zip(self.entries)
+            .map(|(seq, (key, operation))| {
+                let value = match operation {
+                    Operation::Put(value, ttl) => MemValue::Put {
+                        seq,
+                        value,
+                        expires_at: ttl.map(|ttl| {
+                            now.saturating_add(ttl.as_millis().try_into().unwrap_or(u64::MAX))
+                        }),
+                    },
+                    Operation::Delete => MemValue::Delete { seq },
+                    Operation::Merge(operand) => MemValue::Merge { seq, operand },
+                    Operation::DeleteRange(end) => {
+                        return WriteEntry::RangeTombstone(RangeTombstone {
+                            start: key,
+                            end,
+                            seq,
+                        })
+                    }
+                };
+                WriteEntry::Version(key, value)
+            })
+            .collect()
+    }
+}
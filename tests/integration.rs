@@ -1,10 +1,21 @@
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::fs::read_dir;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 use baumdb::BaumDb;
+use baumdb::BaumDbSync;
+use baumdb::DbIterator;
+use baumdb::Error;
+use baumdb::MergeOperator;
+use baumdb::Options;
+use baumdb::WriteBatch;
 use baumdb::DB;
+use bytes::Bytes;
+use futures::StreamExt;
 use tokio::fs::create_dir_all;
 use tokio::fs::remove_dir_all;
 use uuid::Uuid;
@@ -21,21 +32,36 @@ async fn test_clean_up(path: impl AsRef<Path>) {
     let _ = remove_dir_all(path).await;
 }
 
+/// Moves the iterator backwards by up to `n` entries, returning them.
+async fn prev_entries(
+    iterator: &mut DbIterator,
+    n: usize,
+) -> Vec<(Bytes, Bytes)> {
+    let mut entries = Vec::new();
+    while entries.len() < n {
+        let Some(entry) = iterator.prev().await else {
+            break;
+        };
+        entries.push(entry);
+    }
+    entries
+}
+
 #[tokio::test]
 async fn test_basic_ops() {
     let path = prepare_test().await;
-    let mut db = BaumDb::new(&path, 2).await;
+    let db = BaumDb::new(&path, 2).await.unwrap();
 
     let key = "foo";
     let value = "value";
     db.put(key.to_string(), value.to_string()).await.unwrap();
 
-    let returned_value = db.get(key).await.unwrap();
+    let returned_value = db.get_string(key).await.unwrap();
     assert_eq!(returned_value.as_deref(), Some(value));
 
     db.delete(key).await.unwrap();
 
-    let returned_value = db.get(key).await.unwrap();
+    let returned_value = db.get_string(key).await.unwrap();
     assert!(returned_value.is_none());
     test_clean_up(&path).await;
 }
@@ -43,7 +69,7 @@ async fn test_basic_ops() {
 #[tokio::test]
 async fn test_basic_ops_with_many_keys() {
     let path = prepare_test().await;
-    let mut db = BaumDb::new(&path, 2).await;
+    let db = BaumDb::new(&path, 2).await.unwrap();
 
     let key_values = vec![
         ("Aa", "1"),
@@ -57,7 +83,7 @@ async fn test_basic_ops_with_many_keys() {
     }
 
     for (key, value) in key_values.iter() {
-        let returned_value = db.get(key).await.unwrap();
+        let returned_value = db.get_string(key).await.unwrap();
         assert_eq!(returned_value.as_deref(), Some(*value));
     }
 
@@ -66,7 +92,7 @@ async fn test_basic_ops_with_many_keys() {
     }
 
     for (key, _) in key_values {
-        let returned_value = db.get(key).await.unwrap();
+        let returned_value = db.get_string(key).await.unwrap();
         assert!(returned_value.is_none());
     }
     test_clean_up(&path).await;
@@ -75,13 +101,13 @@ async fn test_basic_ops_with_many_keys() {
 #[tokio::test]
 async fn test_updating_a_key_works() {
     let path = prepare_test().await;
-    let mut db = BaumDb::new(&path, 128).await;
+    let db = BaumDb::new(&path, 128).await.unwrap();
 
     let key = "SomeKey".to_string();
     let value = "1".to_string();
 
     db.put(key.clone(), value.clone()).await.unwrap();
-    let returned_value = db.get(&key).await.unwrap();
+    let returned_value = db.get_string(&key).await.unwrap();
     assert_eq!(returned_value, Some(value));
 
     // Add some random values into the db to ensure data has been flushed to disk
@@ -100,7 +126,7 @@ async fn test_updating_a_key_works() {
             .await
             .unwrap();
     }
-    let returned_value = db.get(&key).await.unwrap();
+    let returned_value = db.get_string(&key).await.unwrap();
     assert_eq!(returned_value, Some(value));
 
     test_clean_up(&path).await;
@@ -109,9 +135,9 @@ async fn test_updating_a_key_works() {
 #[tokio::test]
 async fn test_bloom_filter_is_used() {
     let path = prepare_test().await;
-    let mut db = BaumDb::new(&path, 2).await;
+    let db = BaumDb::new(&path, 2).await.unwrap();
 
-    let key_values = vec![
+    let key_values = [
         ("Aa", "1"),
         ("Bbb", "2"),
         ("Cc", "3"),
@@ -125,7 +151,7 @@ async fn test_bloom_filter_is_used() {
         db.put(key.to_string(), value.to_string()).await.unwrap();
     }
 
-    db.get("Aa").await.unwrap();
+    db.get_string("Aa").await.unwrap();
     let files: HashMap<_, _> = read_dir(&path)
         .unwrap()
         .flatten()
@@ -134,16 +160,14 @@ async fn test_bloom_filter_is_used() {
 
     for idx in 0..3 {
         let bloom = files.get(&format!("L0-bloom-{idx}.db")).unwrap();
-        // Every bloom file gets accessed again after creation as the key looked for above is in the oldest file
-        assert!(
-            bloom.metadata().unwrap().accessed().unwrap()
-                > bloom.metadata().unwrap().created().unwrap()
-        );
-
         let index = files.get(&format!("L0-index-{idx}.db")).unwrap();
         let data = files.get(&format!("L0-data-{idx}.db")).unwrap();
         if idx == 0 {
-            // The index and data files are accessed again after creation as the value is found in the oldest file
+            // The bloom, index and data files are accessed again after creation as the value is found in the oldest file
+            assert!(
+                bloom.metadata().unwrap().accessed().unwrap()
+                    > bloom.metadata().unwrap().created().unwrap()
+            );
             assert!(
                 index.metadata().unwrap().accessed().unwrap()
                     > index.metadata().unwrap().created().unwrap()
@@ -154,17 +178,1215 @@ async fn test_bloom_filter_is_used() {
             );
         }
         // } else {
-        //     // The other index and data files that don't contain the value are not touched
+        //     // The other bundles are skipped as their key range does not contain the key
         //     assert_eq!(
-        //         index.metadata().unwrap().created().unwrap(),
-        //         index.metadata().unwrap().accessed().unwrap()
-        //     );
-        //     assert_eq!(
-        //         data.metadata().unwrap().created().unwrap(),
-        //         data.metadata().unwrap().accessed().unwrap()
+        //         bloom.metadata().unwrap().created().unwrap(),
+        //         bloom.metadata().unwrap().accessed().unwrap()
         //     );
         // }
     }
 
     test_clean_up(&path).await;
 }
+
+#[tokio::test]
+async fn test_unflushed_writes_are_recovered_from_wal() {
+    let path = prepare_test().await;
+    let db = BaumDb::new(&path, 128).await.unwrap();
+
+    db.put("foo".to_string(), "1".to_string()).await.unwrap();
+    db.put("bar".to_string(), "2".to_string()).await.unwrap();
+    db.put("foo".to_string(), "3".to_string()).await.unwrap();
+    db.delete("bar").await.unwrap();
+    // Simulate a crash by dropping the db without flushing the memtable
+    drop(db);
+
+    let db = BaumDb::new(&path, 128).await.unwrap();
+    assert_eq!(db.get_string("foo").await.unwrap().as_deref(), Some("3"));
+    assert!(db.get_string("bar").await.unwrap().is_none());
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_reopening_recovers_flushed_data() {
+    let path = prepare_test().await;
+    let db = BaumDb::open(&path, 2).await.unwrap();
+
+    let key_values = [
+        ("Aa", "1"),
+        ("Bb", "2"),
+        ("Cc", "3"),
+        ("Dd", "4"),
+        ("Ee", "5"),
+        ("Ff", "6"),
+    ];
+    for (key, value) in key_values.iter() {
+        db.put(key.to_string(), value.to_string()).await.unwrap();
+    }
+    db.put("Aa".to_string(), "7".to_string()).await.unwrap();
+    drop(db);
+
+    let db = BaumDb::open(&path, 2).await.unwrap();
+    assert_eq!(db.get_string("Aa").await.unwrap().as_deref(), Some("7"));
+    for (key, value) in key_values.iter().skip(1) {
+        assert_eq!(db.get_string(key).await.unwrap().as_deref(), Some(*value));
+    }
+
+    // New flushes must not collide with the recovered files
+    db.put("Gg".to_string(), "8".to_string()).await.unwrap();
+    db.put("Hh".to_string(), "9".to_string()).await.unwrap();
+    assert_eq!(db.get_string("Gg").await.unwrap().as_deref(), Some("8"));
+    assert_eq!(db.get_string("Hh").await.unwrap().as_deref(), Some("9"));
+
+    test_clean_up(&path).await;
+}
+
+async fn flip_byte(
+    path: impl AsRef<Path>,
+    offset: usize,
+) {
+    let mut bytes = tokio::fs::read(&path).await.unwrap();
+    bytes[offset] ^= 0xFF;
+    tokio::fs::write(&path, bytes).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_corrupted_files_are_detected() {
+    let path = prepare_test().await;
+    let db = BaumDb::new(&path, 2).await.unwrap();
+    db.put("Aa".to_string(), "1".to_string()).await.unwrap();
+    db.put("Bb".to_string(), "2".to_string()).await.unwrap();
+    // Flush a second time so that the first bundle is only readable from disk
+    db.put("Cc".to_string(), "3".to_string()).await.unwrap();
+    db.put("Dd".to_string(), "4".to_string()).await.unwrap();
+    assert_eq!(db.get_string("Aa").await.unwrap().as_deref(), Some("1"));
+
+    // Corrupt the compressed content of the first data block behind its length and checksum
+    let data_file_path = path.join("L0-data-0.db");
+    flip_byte(&data_file_path, 20).await;
+    let error = db.get_string("Aa").await.unwrap_err();
+    let Error::Corruption(corruption) = error else {
+        panic!("Expected a corruption error, got {error:?}");
+    };
+    assert_eq!(corruption.path(), data_file_path);
+    assert_eq!(corruption.offset(), 0);
+
+    let bloom_file_path = path.join("L0-bloom-0.db");
+    flip_byte(&bloom_file_path, 0).await;
+    let error = db.get_string("Bb").await.unwrap_err();
+    let Error::Corruption(corruption) = error else {
+        panic!("Expected a corruption error, got {error:?}");
+    };
+    assert_eq!(corruption.path(), bloom_file_path);
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_keys_in_later_blocks_are_found() {
+    let path = prepare_test().await;
+    let n_keys = 2000;
+    let db = BaumDb::new(&path, n_keys).await.unwrap();
+
+    // With values of this size every flushed memtable spans dozens of blocks
+    let value = "v".repeat(100);
+    for i in 0..n_keys {
+        db.put(format!("key-{i:06}"), format!("{i}-{value}"))
+            .await
+            .unwrap();
+    }
+    // Flush a second memtable so that the first one is only readable from disk
+    for i in n_keys..2 * n_keys {
+        db.put(format!("key-{i:06}"), format!("{i}-{value}"))
+            .await
+            .unwrap();
+    }
+
+    for i in 0..2 * n_keys {
+        let returned_value = db.get_string(format!("key-{i:06}")).await.unwrap();
+        assert_eq!(returned_value, Some(format!("{i}-{value}")));
+    }
+    assert!(db.get_string("key-").await.unwrap().is_none());
+    assert!(db.get_string("key-0000005").await.unwrap().is_none());
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_deleting_a_key_shadows_its_flushed_value() {
+    let path = prepare_test().await;
+    let db = BaumDb::new(&path, 2).await.unwrap();
+
+    db.put("foo".to_string(), "1".to_string()).await.unwrap();
+    db.put("bar".to_string(), "2".to_string()).await.unwrap();
+    // The key is now both in the memtable and on disk
+    db.put("foo".to_string(), "3".to_string()).await.unwrap();
+    db.delete("foo").await.unwrap();
+    assert!(db.get_string("foo").await.unwrap().is_none());
+
+    // And still after the tombstone was flushed as well
+    db.put("baz".to_string(), "4".to_string()).await.unwrap();
+    db.put("qux".to_string(), "5".to_string()).await.unwrap();
+    assert!(db.get_string("foo").await.unwrap().is_none());
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_binary_keys_are_ordered_by_their_raw_bytes() {
+    let path = prepare_test().await;
+    let db = BaumDb::new(&path, 2).await.unwrap();
+
+    // Composite keys of a prefix and a big-endian integer, whose bytes are not valid UTF-8
+    let key = |id: u64| [b"user/".as_slice(), &id.to_be_bytes()].concat();
+    let ids = [255, 1, 256, 0, u64::MAX, 65_536];
+    for id in ids {
+        db.put(key(id), vec![0xFF, id as u8]).await.unwrap();
+    }
+
+    for id in ids {
+        let returned_value = db.get(key(id)).await.unwrap();
+        assert_eq!(returned_value.as_deref(), Some([0xFF, id as u8].as_slice()));
+    }
+    assert!(db.get(key(2)).await.unwrap().is_none());
+    assert!(matches!(
+        db.get_string(key(1)).await,
+        Err(Error::InvalidData(_))
+    ));
+
+    db.delete(key(256)).await.unwrap();
+    assert!(db.get(key(256)).await.unwrap().is_none());
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_scan_merges_memtables_and_bundles() {
+    let path = prepare_test().await;
+    let db = BaumDb::new(&path, 4).await.unwrap();
+
+    let mut expected = BTreeMap::new();
+    // Enough writes to spread the keys over the memtables and bundles of all levels
+    for round in 0..10 {
+        for i in 0..20 {
+            let key = format!("user/{i:03}");
+            let value = format!("{round}");
+            if (i + round) % 7 == 0 {
+                db.delete(&key).await.unwrap();
+                expected.remove(&key);
+            } else {
+                db.put(key.clone(), value.clone()).await.unwrap();
+                expected.insert(key, value);
+            }
+        }
+        db.put(format!("other/{round}"), "x").await.unwrap();
+    }
+
+    let scanned: Vec<_> = db
+        .scan("user/005".."user/015")
+        .await
+        .unwrap()
+        .map(|(key, value)| {
+            (
+                String::from_utf8(key.to_vec()).unwrap(),
+                String::from_utf8(value.to_vec()).unwrap(),
+            )
+        })
+        .collect()
+        .await;
+    let expected_in_range: Vec<_> = expected
+        .range("user/005".to_string().."user/015".to_string())
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    assert_eq!(scanned, expected_in_range);
+
+    let n_other_keys = 10;
+    assert_eq!(
+        db.scan::<&str, _>(..).await.unwrap().count().await,
+        expected.len() + n_other_keys
+    );
+    assert_eq!(db.scan("user/1".."user/0").await.unwrap().count().await, 0);
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_latest_entries_are_found_by_iterating_backwards() {
+    let path = prepare_test().await;
+    let db = BaumDb::new(&path, 8).await.unwrap();
+
+    let event_key = |user: u64, timestamp: u64| {
+        [
+            b"events/".as_slice(),
+            &user.to_be_bytes(),
+            &timestamp.to_be_bytes(),
+        ]
+        .concat()
+    };
+    for timestamp in 0..50 {
+        for user in 0..3 {
+            db.put(event_key(user, timestamp), timestamp.to_string())
+                .await
+                .unwrap();
+        }
+    }
+
+    // The latest three events of user 1 before timestamp 40
+    let mut iterator = db.scan(event_key(1, 0)..event_key(2, 0)).await.unwrap();
+    iterator.seek_for_prev(event_key(1, 39));
+    let latest = prev_entries(&mut iterator, 3).await;
+    assert_eq!(
+        latest,
+        [39, 38, 37].map(|timestamp: u64| (
+            Bytes::from(event_key(1, timestamp)),
+            Bytes::from(timestamp.to_string())
+        ))
+    );
+
+    // Walking past the start of the range stops at the first event of the user
+    iterator.seek(event_key(1, 1));
+    assert_eq!(iterator.prev().await.unwrap().0, event_key(1, 0));
+    assert!(iterator.prev().await.is_none());
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_scans_read_blocks_lazily_from_a_consistent_state() {
+    let path = prepare_test().await;
+    let options = Options::default()
+        .max_memtable_size(100)
+        .block_size(64)
+        .l0_compaction_threshold(2);
+    let db = BaumDb::open_with_options(&path, options.clone())
+        .await
+        .unwrap();
+    for i in 0..300 {
+        db.put(format!("key-{i:03}"), format!("old-{i}"))
+            .await
+            .unwrap();
+    }
+
+    let mut iterator = db.scan("key-050".."key-250").await.unwrap();
+    assert_eq!(iterator.next().await.unwrap().0, "key-050");
+    // Neither later writes nor the compactions they cause change what the scan reads
+    for i in 0..300 {
+        db.put(format!("key-{i:03}"), format!("new-{i}"))
+            .await
+            .unwrap();
+    }
+    db.delete("key-100").await.unwrap();
+    db.close().await.unwrap();
+
+    let keys: Vec<_> = (&mut iterator).map(|(key, _)| key).collect().await;
+    assert_eq!(
+        keys,
+        (51..250)
+            .map(|i| Bytes::from(format!("key-{i:03}")))
+            .collect::<Vec<_>>()
+    );
+    assert!(iterator.error().is_none());
+
+    // Seeking and moving backwards across block boundaries
+    iterator.seek_for_prev("key-200");
+    let latest = prev_entries(&mut iterator, 20).await;
+    assert_eq!(
+        latest,
+        (181..=200)
+            .rev()
+            .map(|i| (
+                Bytes::from(format!("key-{i:03}")),
+                Bytes::from(format!("old-{i}"))
+            ))
+            .collect::<Vec<_>>()
+    );
+    iterator.seek("key-1234");
+    assert_eq!(iterator.next().await.unwrap().0, "key-124");
+    iterator.seek_to_last();
+    assert_eq!(iterator.prev().await.unwrap().0, "key-249");
+    // A cancelled read is dropped once the cursor is moved elsewhere
+    iterator.seek("key-150");
+    let _ = futures::poll!(Box::pin(iterator.next()));
+    iterator.seek("key-160");
+    assert_eq!(iterator.next().await.unwrap().0, "key-160");
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_write_batch_is_applied_atomically() {
+    let path = prepare_test().await;
+    let db = BaumDb::new(&path, 2).await.unwrap();
+
+    db.put("index/old", "record/1").await.unwrap();
+    // The batch exceeds the memtable limit but must not be split by a flush
+    let mut batch = WriteBatch::new();
+    batch.put("record/1", "payload");
+    batch.delete("index/old");
+    batch.put("index/new", "record/1");
+    batch.put("record/1", "updated payload");
+    assert_eq!(batch.len(), 4);
+    db.write(batch).await.unwrap();
+
+    assert_eq!(
+        db.get_string("record/1").await.unwrap().as_deref(),
+        Some("updated payload")
+    );
+    assert!(db.get("index/old").await.unwrap().is_none());
+    assert_eq!(
+        db.get_string("index/new").await.unwrap().as_deref(),
+        Some("record/1")
+    );
+
+    // A batch that is not flushed yet is recovered from the log as a whole
+    let mut batch = WriteBatch::new();
+    batch.put("a", "1");
+    batch.put("b", "2");
+    db.write(batch).await.unwrap();
+    drop(db);
+
+    let db = BaumDb::new(&path, 2).await.unwrap();
+    assert_eq!(db.get_string("a").await.unwrap().as_deref(), Some("1"));
+    assert_eq!(db.get_string("b").await.unwrap().as_deref(), Some("2"));
+    assert_eq!(
+        db.get_string("record/1").await.unwrap().as_deref(),
+        Some("updated payload")
+    );
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_snapshot_reads_ignore_newer_writes() {
+    let path = prepare_test().await;
+    let db = BaumDb::new(&path, 4).await.unwrap();
+
+    db.put("a", "1").await.unwrap();
+    db.put("b", "1").await.unwrap();
+    let snapshot = db.snapshot();
+    db.put("a", "2").await.unwrap();
+    db.delete("b").await.unwrap();
+    db.put("c", "2").await.unwrap();
+
+    assert_eq!(db.get_string("a").await.unwrap().as_deref(), Some("2"));
+    assert!(db.get("b").await.unwrap().is_none());
+    assert_eq!(
+        db.get_with_snapshot("a", &snapshot).await.unwrap(),
+        Some(Bytes::from("1"))
+    );
+    assert_eq!(
+        db.get_with_snapshot("b", &snapshot).await.unwrap(),
+        Some(Bytes::from("1"))
+    );
+    assert!(db
+        .get_with_snapshot("c", &snapshot)
+        .await
+        .unwrap()
+        .is_none());
+
+    // The snapshot stays consistent while its versions are flushed and compacted
+    for i in 0..100 {
+        db.put(format!("key-{i:03}"), "value").await.unwrap();
+        db.put("a", format!("{}", i + 3)).await.unwrap();
+    }
+    assert_eq!(
+        db.get_with_snapshot("a", &snapshot).await.unwrap(),
+        Some(Bytes::from("1"))
+    );
+    let snapshot_entries: Vec<_> = db
+        .scan_with_snapshot::<&str, _>(.., &snapshot)
+        .await
+        .unwrap()
+        .collect()
+        .await;
+    assert_eq!(
+        snapshot_entries,
+        vec![
+            (Bytes::from("a"), Bytes::from("1")),
+            (Bytes::from("b"), Bytes::from("1")),
+        ]
+    );
+    assert_eq!(db.get_string("a").await.unwrap().as_deref(), Some("102"));
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_sequence_numbers_continue_after_reopening() {
+    let path = prepare_test().await;
+    let db = BaumDb::new(&path, 2).await.unwrap();
+    db.put("a", "1").await.unwrap();
+    db.put("b", "1").await.unwrap();
+    db.put("a", "2").await.unwrap();
+    drop(db);
+
+    // New writes must still win over the flushed and logged ones
+    let db = BaumDb::new(&path, 2).await.unwrap();
+    db.put("a", "3").await.unwrap();
+    db.put("b", "3").await.unwrap();
+    assert_eq!(db.get_string("a").await.unwrap().as_deref(), Some("3"));
+    assert_eq!(db.get_string("b").await.unwrap().as_deref(), Some("3"));
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_transactions_detect_conflicting_writes() {
+    let path = prepare_test().await;
+    let db = BaumDb::new(&path, 4).await.unwrap();
+    db.put("counter", "1").await.unwrap();
+
+    let mut transaction = db.begin_transaction();
+    let counter = transaction.get(&db, "counter").await.unwrap().unwrap();
+    assert_eq!(counter, Bytes::from("1"));
+    transaction.put("counter", "2");
+    transaction.put("log", "incremented");
+    // The transaction reads its own writes, others don't see them before the commit
+    assert_eq!(
+        transaction.get(&db, "counter").await.unwrap(),
+        Some(Bytes::from("2"))
+    );
+    assert!(db.get("log").await.unwrap().is_none());
+    transaction.commit(&db).await.unwrap();
+    assert_eq!(
+        db.get_string("counter").await.unwrap().as_deref(),
+        Some("2")
+    );
+    assert_eq!(
+        db.get_string("log").await.unwrap().as_deref(),
+        Some("incremented")
+    );
+
+    // A write to a key the transaction read makes the commit fail
+    let mut transaction = db.begin_transaction();
+    transaction.get(&db, "counter").await.unwrap();
+    transaction.put("counter", "3");
+    db.put("counter", "10").await.unwrap();
+    let error = transaction.commit(&db).await.unwrap_err();
+    let Error::TransactionConflict(conflict) = error else {
+        panic!("Expected a transaction conflict, got {error:?}");
+    };
+    assert_eq!(conflict.key(), b"counter");
+    assert_eq!(
+        db.get_string("counter").await.unwrap().as_deref(),
+        Some("10")
+    );
+
+    // Writes to keys that were not read do not conflict
+    let mut transaction = db.begin_transaction();
+    transaction.get(&db, "log").await.unwrap();
+    transaction.delete("log");
+    db.put("counter", "11").await.unwrap();
+    transaction.commit(&db).await.unwrap();
+    assert!(db.get("log").await.unwrap().is_none());
+
+    test_clean_up(&path).await;
+}
+
+/// Adds up big-endian integers.
+#[derive(Debug)]
+struct CounterOperator;
+
+impl MergeOperator for CounterOperator {
+    fn merge(
+        &self,
+        _key: &[u8],
+        existing_value: Option<&[u8]>,
+        operands: &[Bytes],
+    ) -> Bytes {
+        let to_u64 = |bytes: &[u8]| u64::from_be_bytes(bytes.try_into().unwrap());
+        let sum = operands.iter().map(|operand| to_u64(operand)).sum::<u64>()
+            + existing_value.map_or(0, to_u64);
+        Bytes::copy_from_slice(&sum.to_be_bytes())
+    }
+}
+
+#[tokio::test]
+async fn test_merge_operands_are_applied_across_flushes_and_compactions() {
+    let path = prepare_test().await;
+    let db = BaumDb::open_with_merge_operator(&path, 4, Arc::new(CounterOperator))
+        .await
+        .unwrap();
+    let count =
+        |value: Option<Bytes>| u64::from_be_bytes(value.unwrap().as_ref().try_into().unwrap());
+
+    db.put("counter", 10u64.to_be_bytes().to_vec())
+        .await
+        .unwrap();
+    db.merge("counter", 1u64.to_be_bytes().to_vec())
+        .await
+        .unwrap();
+    assert_eq!(count(db.get("counter").await.unwrap()), 11);
+    let snapshot = db.snapshot();
+
+    for i in 0..100 {
+        db.put(format!("key-{i:03}"), "value").await.unwrap();
+        db.merge("counter", 1u64.to_be_bytes().to_vec())
+            .await
+            .unwrap();
+    }
+    assert_eq!(count(db.get("counter").await.unwrap()), 111);
+    assert_eq!(
+        count(db.get_with_snapshot("counter", &snapshot).await.unwrap()),
+        11
+    );
+    let (key, value) = db
+        .scan("counter"..="counter")
+        .await
+        .unwrap()
+        .next()
+        .await
+        .unwrap();
+    assert_eq!(key, Bytes::from("counter"));
+    assert_eq!(count(Some(value)), 111);
+
+    // Merging into a deleted key starts from scratch
+    db.delete("counter").await.unwrap();
+    db.merge("counter", 5u64.to_be_bytes().to_vec())
+        .await
+        .unwrap();
+    assert_eq!(count(db.get("counter").await.unwrap()), 5);
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_open_fails_with_io_error_if_directory_cannot_be_created() {
+    let path = prepare_test().await;
+    let file_path = path.join("file");
+    tokio::fs::write(&file_path, "not a directory")
+        .await
+        .unwrap();
+
+    let result = BaumDb::new(file_path.join("db"), 4).await;
+    assert!(matches!(result, Err(Error::Io(_))));
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_failed_flush_stops_writes_until_cleared() {
+    let path = prepare_test().await;
+    let db = BaumDb::new(&path, 2).await.unwrap();
+    // Keeps the first flush from creating its data file
+    let blocking_dir_path = path.join("L0-data-0.db");
+    create_dir_all(&blocking_dir_path).await.unwrap();
+
+    db.put("a", "1").await.unwrap();
+    // Triggers the flush, but is applied nevertheless
+    db.put("b", "2").await.unwrap();
+    assert!(matches!(
+        db.background_error().as_deref(),
+        Some(Error::Io(_))
+    ));
+    assert!(matches!(
+        db.put("c", "3").await,
+        Err(Error::BackgroundError(_))
+    ));
+    // The memtable that failed to flush is still read
+    assert_eq!(db.get_string("a").await.unwrap().as_deref(), Some("1"));
+    assert_eq!(db.get_string("b").await.unwrap().as_deref(), Some("2"));
+    assert_eq!(db.get("c").await.unwrap(), None);
+
+    remove_dir_all(&blocking_dir_path).await.unwrap();
+    db.clear_background_error().await.unwrap();
+    assert!(db.background_error().is_none());
+    db.put("c", "3").await.unwrap();
+    drop(db);
+
+    let db = BaumDb::new(&path, 2).await.unwrap();
+    for (key, value) in [("a", "1"), ("b", "2"), ("c", "3")] {
+        assert_eq!(db.get_string(key).await.unwrap().as_deref(), Some(value));
+    }
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_close_flushes_and_fails_later_calls() {
+    let path = prepare_test().await;
+    let db = BaumDb::new(&path, 4).await.unwrap();
+    for i in 0..50 {
+        db.put(format!("key-{i:02}"), format!("{i}")).await.unwrap();
+    }
+    db.put("unflushed", "value").await.unwrap();
+    let other_handle = db.clone();
+
+    db.close().await.unwrap();
+    assert!(matches!(db.get("key-00").await, Err(Error::Closed)));
+    assert!(matches!(
+        other_handle.put("key-00", "value").await,
+        Err(Error::Closed)
+    ));
+    assert!(matches!(
+        other_handle.scan::<&str, _>(..).await,
+        Err(Error::Closed)
+    ));
+    assert!(matches!(
+        db.open_column_family("users", 4).await,
+        Err(Error::Closed)
+    ));
+    db.close().await.unwrap();
+    drop(db);
+    drop(other_handle);
+
+    // The memtable was flushed, so its write-ahead log holds nothing anymore
+    let log_size = read_dir(&path)
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .filter(|entry| entry.file_name().to_string_lossy().ends_with(".log"))
+        .map(|entry| entry.metadata().unwrap().len())
+        .sum::<u64>();
+    assert_eq!(log_size, 0);
+    let db = BaumDb::new(&path, 4).await.unwrap();
+    assert_eq!(
+        db.get_string("unflushed").await.unwrap().as_deref(),
+        Some("value")
+    );
+    assert_eq!(db.scan::<&str, _>(..).await.unwrap().count().await, 51);
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_merging_requires_a_merge_operator() {
+    let path = prepare_test().await;
+    let db = BaumDb::new(&path, 4).await.unwrap();
+
+    let mut batch = WriteBatch::new();
+    batch.put("a", "1");
+    batch.merge("b", "2");
+    assert!(matches!(
+        db.write(batch).await,
+        Err(Error::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        db.merge("b", "2").await,
+        Err(Error::InvalidArgument(_))
+    ));
+    assert!(db.get("a").await.unwrap().is_none());
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_expired_keys_read_as_absent() {
+    let path = prepare_test().await;
+    let db = BaumDb::new(&path, 4).await.unwrap();
+
+    db.put("shadowed", "old").await.unwrap();
+    // A zero TTL expires the values as they are written, so that no timing is involved. Expiry at
+    // a later time is covered by the unit tests, which pass the current time explicitly.
+    db.put_with_ttl("shadowed", "new", Duration::ZERO)
+        .await
+        .unwrap();
+    db.put_with_ttl("expiring", "value", Duration::ZERO)
+        .await
+        .unwrap();
+    db.put_with_ttl("lasting", "value", Duration::from_secs(3600))
+        .await
+        .unwrap();
+    assert!(db.get("shadowed").await.unwrap().is_none());
+    assert!(db.get("expiring").await.unwrap().is_none());
+    assert_eq!(
+        db.get_string("lasting").await.unwrap().as_deref(),
+        Some("value")
+    );
+
+    // Expiry applies to flushed entries as well
+    for i in 0..20 {
+        db.put(format!("key-{i:02}"), "value").await.unwrap();
+    }
+    assert!(db.get("shadowed").await.unwrap().is_none());
+    assert!(db.get("expiring").await.unwrap().is_none());
+    assert_eq!(
+        db.get_string("lasting").await.unwrap().as_deref(),
+        Some("value")
+    );
+    let keys: Vec<_> = db
+        .scan("l"..)
+        .await
+        .unwrap()
+        .map(|(key, _)| key)
+        .collect()
+        .await;
+    assert_eq!(keys, vec![Bytes::from("lasting")]);
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_column_families_are_separate_keyspaces() {
+    let path = prepare_test().await;
+    let db = BaumDb::new(&path, 2).await.unwrap();
+    let users = db.open_column_family("users", 2).await.unwrap();
+    let events = db.open_column_family("events", 8).await.unwrap();
+    assert!(matches!(
+        db.open_column_family("../users", 2).await,
+        Err(Error::InvalidArgument(_))
+    ));
+
+    db.put("key", "default").await.unwrap();
+    db.put_cf(&users, "key", "user").await.unwrap();
+    db.put_cf(&users, "alice", "1").await.unwrap();
+    db.put_cf(&users, "bob", "2").await.unwrap();
+    db.put_cf(&events, "key", "event").await.unwrap();
+    db.delete_cf(&events, "key").await.unwrap();
+
+    assert_eq!(
+        db.get_string("key").await.unwrap().as_deref(),
+        Some("default")
+    );
+    assert_eq!(
+        db.get_cf(&users, "key").await.unwrap(),
+        Some(Bytes::from("user"))
+    );
+    assert!(db.get_cf(&events, "key").await.unwrap().is_none());
+    let user_keys: Vec<_> = db
+        .scan_cf::<&str, _>(&users, ..)
+        .await
+        .unwrap()
+        .map(|(key, _)| key)
+        .collect()
+        .await;
+    assert_eq!(
+        user_keys,
+        vec![Bytes::from("alice"), Bytes::from("bob"), Bytes::from("key")]
+    );
+    drop(db);
+
+    // The column families are recovered when reopening the database
+    let db = BaumDb::new(&path, 2).await.unwrap();
+    let users = db.open_column_family("users", 2).await.unwrap();
+    assert_eq!(
+        db.get_cf(&users, "alice").await.unwrap(),
+        Some(Bytes::from("1"))
+    );
+    assert_eq!(
+        db.get_string("key").await.unwrap().as_deref(),
+        Some("default")
+    );
+    assert_eq!(
+        db.open_column_family("default", 2).await.unwrap(),
+        db.default_column_family()
+    );
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_multi_get_matches_single_gets() {
+    let path = prepare_test().await;
+    let db = BaumDb::new(&path, 8).await.unwrap();
+
+    for i in 0..200 {
+        db.put(format!("key-{i:03}"), format!("{i}")).await.unwrap();
+    }
+    for i in (0..200).step_by(3) {
+        db.delete(format!("key-{i:03}")).await.unwrap();
+    }
+    db.put("key-001", "updated").await.unwrap();
+
+    let keys = [
+        "key-199", "missing", "key-001", "key-003", "key-100", "key-001", "key-050",
+    ];
+    let values = db.multi_get(&keys).await.unwrap();
+    assert_eq!(values.len(), keys.len());
+    for (key, value) in keys.iter().zip(values) {
+        assert_eq!(value, db.get(key).await.unwrap(), "{key}");
+    }
+    assert_eq!(
+        db.multi_get(&["key-001", "key-003"]).await.unwrap(),
+        vec![Some(Bytes::from("updated")), None]
+    );
+    assert!(db.multi_get::<&str>(&[]).await.unwrap().is_empty());
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_delete_range_hides_older_keys_in_range() {
+    let path = prepare_test().await;
+    let db = BaumDb::new(&path, 8).await.unwrap();
+
+    for i in 0..100 {
+        db.put(format!("key-{i:03}"), format!("{i}")).await.unwrap();
+    }
+    let snapshot = db.snapshot();
+    db.delete_range("key-010", "key-090").await.unwrap();
+    // Writes after the range delete are visible again
+    db.put("key-050", "rewritten").await.unwrap();
+
+    let check = |db: BaumDb| async move {
+        assert_eq!(
+            db.get_string("key-009").await.unwrap().as_deref(),
+            Some("9")
+        );
+        assert!(db.get("key-010").await.unwrap().is_none());
+        assert!(db.get("key-089").await.unwrap().is_none());
+        assert_eq!(
+            db.get_string("key-090").await.unwrap().as_deref(),
+            Some("90")
+        );
+        assert_eq!(
+            db.get_string("key-050").await.unwrap().as_deref(),
+            Some("rewritten")
+        );
+        let keys: Vec<_> = db
+            .scan("key-005".."key-095")
+            .await
+            .unwrap()
+            .map(|(key, _)| key)
+            .collect()
+            .await;
+        assert_eq!(keys.len(), 5 + 1 + 5);
+        assert_eq!(
+            db.multi_get(&["key-008", "key-020", "key-050"])
+                .await
+                .unwrap(),
+            vec![Some(Bytes::from("8")), None, Some(Bytes::from("rewritten"))]
+        );
+        db
+    };
+    let db = check(db).await;
+    assert_eq!(
+        db.get_with_snapshot("key-020", &snapshot).await.unwrap(),
+        Some(Bytes::from("20"))
+    );
+    drop(snapshot);
+    // Waits for the compactions of the old handle, which would otherwise race with the new one
+    db.close().await.unwrap();
+
+    // The range tombstone survives a restart and its compaction
+    let db = BaumDb::new(&path, 8).await.unwrap();
+    for i in 0..64 {
+        db.put(format!("other-{i:03}"), "value").await.unwrap();
+    }
+    check(db).await;
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_delete_range_rejects_empty_ranges() {
+    let path = prepare_test().await;
+    let db = BaumDb::new(&path, 8).await.unwrap();
+    db.put("m", "value").await.unwrap();
+
+    for (start, end) in [("z", "a"), ("m", "m")] {
+        assert!(matches!(
+            db.delete_range(start, end).await,
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+    // No part of a batch with an empty range is written
+    let mut batch = WriteBatch::new();
+    batch.delete("m");
+    batch.delete_range("z", "a");
+    assert!(matches!(
+        db.write(batch).await,
+        Err(Error::InvalidArgument(_))
+    ));
+    assert_eq!(db.get_string("m").await.unwrap().as_deref(), Some("value"));
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_merge_after_delete_range_ignores_deleted_value() {
+    let path = prepare_test().await;
+    let db = BaumDb::open_with_merge_operator(&path, 8, Arc::new(CounterOperator))
+        .await
+        .unwrap();
+    let count =
+        |value: Option<Bytes>| u64::from_be_bytes(value.unwrap().as_ref().try_into().unwrap());
+
+    db.put("counter", 10u64.to_be_bytes().to_vec())
+        .await
+        .unwrap();
+    db.delete_range("a", "z").await.unwrap();
+    db.merge("counter", 1u64.to_be_bytes().to_vec())
+        .await
+        .unwrap();
+    assert_eq!(count(db.get("counter").await.unwrap()), 1);
+
+    // The flush resolves the operand against the range tombstone, not the deleted put
+    db.close().await.unwrap();
+    let db = BaumDb::open_with_merge_operator(&path, 8, Arc::new(CounterOperator))
+        .await
+        .unwrap();
+    assert_eq!(count(db.get("counter").await.unwrap()), 1);
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_handles_are_shared_across_tasks() {
+    let path = prepare_test().await;
+    let db = BaumDb::open_with_merge_operator(&path, 8, Arc::new(CounterOperator))
+        .await
+        .unwrap();
+    let count = |value: Option<Bytes>| {
+        value.map_or(0, |value| {
+            u64::from_be_bytes(value.as_ref().try_into().unwrap())
+        })
+    };
+    let (n_writers, n_writes) = (4, 100);
+
+    let writers = (0..n_writers)
+        .map(|writer| {
+            let db = db.clone();
+            tokio::spawn(async move {
+                for i in 0..n_writes {
+                    db.put(format!("key-{writer}-{i:03}"), format!("{i}"))
+                        .await
+                        .unwrap();
+                    db.merge("counter", 1u64.to_be_bytes().to_vec())
+                        .await
+                        .unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    // Reads run while the writes flush and compact the memtables, never seeing a merge twice
+    let readers = (0..4)
+        .map(|_| {
+            let db = db.clone();
+            tokio::spawn(async move {
+                let mut previous_count = 0;
+                while previous_count < n_writers * n_writes {
+                    let current_count = count(db.get("counter").await.unwrap());
+                    assert!(current_count >= previous_count);
+                    assert!(current_count <= n_writers * n_writes);
+                    previous_count = current_count;
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for task in writers.into_iter().chain(readers) {
+        task.await.unwrap();
+    }
+
+    assert_eq!(
+        count(db.get("counter").await.unwrap()),
+        n_writers * n_writes
+    );
+    for writer in 0..n_writers {
+        for i in 0..n_writes {
+            assert_eq!(
+                db.get_string(format!("key-{writer}-{i:03}")).await.unwrap(),
+                Some(format!("{i}"))
+            );
+        }
+    }
+    assert_eq!(
+        db.scan::<&str, _>(..).await.unwrap().count().await as u64,
+        n_writers * n_writes + 1
+    );
+
+    test_clean_up(&path).await;
+}
+
+#[test]
+fn test_blocking_api_without_runtime() {
+    let path = PathBuf::from(format!("{TEST_LOG_PATH}/{:?}", Uuid::new_v4()));
+    let db = BaumDbSync::open(&path, 4).unwrap();
+
+    for i in 0..20 {
+        db.put(format!("key-{i:02}"), format!("{i}")).unwrap();
+    }
+    db.delete("key-03").unwrap();
+    db.delete_range("key-10", "key-15").unwrap();
+    assert_eq!(db.get_string("key-02").unwrap().as_deref(), Some("2"));
+    assert_eq!(db.get("key-03").unwrap(), None);
+    assert_eq!(db.get("key-12").unwrap(), None);
+    assert_eq!(db.scan("key-00".."key-20").unwrap().count(), 14);
+    db.close().unwrap();
+
+    // The data survives reopening with a new runtime
+    let db = BaumDbSync::open(&path, 4).unwrap();
+    assert_eq!(db.get_string("key-19").unwrap().as_deref(), Some("19"));
+    drop(db);
+
+    let _ = std::fs::remove_dir_all(&path);
+}
+
+#[tokio::test]
+async fn test_open_with_options() {
+    let path = prepare_test().await;
+    let options = Options::default()
+        .max_memtable_size(4)
+        .block_size(64)
+        .compression_level(1)
+        .bloom_filter_size(1024)
+        .bloom_filter_hashes(3)
+        .l0_compaction_threshold(2)
+        .l1_compaction_threshold(2)
+        .flush_queue_depth(2)
+        .compaction_queue_depth(2);
+    let db = BaumDb::open_with_options(&path, options.clone())
+        .await
+        .unwrap();
+
+    for i in 0..40 {
+        db.put(format!("key-{i:02}"), format!("value-{i}"))
+            .await
+            .unwrap();
+    }
+    db.close().await.unwrap();
+    assert!(path.join("OPTIONS").exists());
+
+    // Any setting may change between opens, the existing SST bundles can still be read
+    let db = BaumDb::open_with_options(
+        &path,
+        options
+            .block_size(128)
+            .compression_level(9)
+            .bloom_filter_size(512)
+            .bloom_filter_hashes(2),
+    )
+    .await
+    .unwrap();
+    for i in 40..80 {
+        db.put(format!("key-{i:02}"), format!("value-{i}"))
+            .await
+            .unwrap();
+    }
+    db.close().await.unwrap();
+
+    // Opening without options keeps the recorded ones instead of resetting them to the defaults
+    let db = BaumDb::open(&path, 4).await.unwrap();
+    for i in 0..80 {
+        assert_eq!(
+            db.get(format!("key-{i:02}")).await.unwrap(),
+            Some(Bytes::from(format!("value-{i}")))
+        );
+    }
+    db.close().await.unwrap();
+    let recorded = std::fs::read_to_string(path.join("OPTIONS")).unwrap();
+    assert!(recorded.contains("block_size=128\n"));
+    assert!(recorded.contains("bloom_filter_hashes=2\n"));
+    assert!(recorded.contains("max_memtable_size=4\n"));
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_open_fails_with_invalid_options() {
+    let path = prepare_test().await;
+
+    for options in [
+        Options::default().block_size(0),
+        Options::default().bloom_filter_hashes(0),
+        Options::default().l0_compaction_threshold(0),
+        Options::default().compression_level(10),
+    ] {
+        assert!(matches!(
+            BaumDb::open_with_options(&path, options).await,
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+    assert!(!path.join("OPTIONS").exists());
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_reopen_without_merge_operator_fails() {
+    let path = prepare_test().await;
+    let db = BaumDb::open_with_merge_operator(&path, 4, Arc::new(CounterOperator))
+        .await
+        .unwrap();
+    db.merge("counter", 1u64.to_be_bytes().to_vec())
+        .await
+        .unwrap();
+    db.close().await.unwrap();
+
+    assert!(matches!(
+        BaumDb::open(&path, 4).await,
+        Err(Error::InvalidArgument(_))
+    ));
+    let db = BaumDb::open_with_options(
+        &path,
+        Options::default().merge_operator(Arc::new(CounterOperator)),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        db.get("counter").await.unwrap(),
+        Some(Bytes::from(1u64.to_be_bytes().to_vec()))
+    );
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_memtable_is_flushed_by_size() {
+    let path = prepare_test().await;
+    let options = Options::default()
+        .max_memtable_size(1000)
+        .write_buffer_size(64 * 1024);
+    let db = BaumDb::open_with_options(&path, options).await.unwrap();
+    let default = db.default_column_family();
+    assert_eq!(db.memtable_size(&default).unwrap(), 0);
+
+    db.put("small", "value").await.unwrap();
+    let small_size = db.memtable_size(&default).unwrap();
+    assert!(small_size > 0);
+
+    // A few large values fill the memtable long before it holds `max_memtable_size` entries
+    let large_value = vec![0; 16 * 1024];
+    for i in 0..4 {
+        db.put(format!("large-{i}"), large_value.clone())
+            .await
+            .unwrap();
+    }
+    assert!(db.memtable_size(&default).unwrap() < small_size + 4 * large_value.len());
+    assert!(read_dir(&path)
+        .unwrap()
+        .any(|entry| entry.unwrap().file_name() == "L0-data-0.db"));
+    assert_eq!(
+        db.get("large-0").await.unwrap(),
+        Some(Bytes::from(large_value))
+    );
+
+    db.close().await.unwrap();
+    assert!(matches!(db.memtable_size(&default), Err(Error::Closed)));
+
+    test_clean_up(&path).await;
+}
+
+#[tokio::test]
+async fn test_max_memtable_size_limits_the_number_of_entries() {
+    let path = prepare_test().await;
+    let has_l0_bundle = |path: &Path| {
+        read_dir(path)
+            .unwrap()
+            .any(|entry| entry.unwrap().file_name() == "L0-data-0.db")
+    };
+
+    // Without a limit, many small entries stay in the memtable until it is large enough
+    let db = BaumDb::open_with_options(&path, Options::default())
+        .await
+        .unwrap();
+    for i in 0..1000 {
+        db.put(format!("key-{i:04}"), "value").await.unwrap();
+    }
+    assert!(!has_l0_bundle(&path));
+    db.close().await.unwrap();
+    test_clean_up(&path).await;
+
+    // The limit applies even if the memtable is far from the write buffer size
+    let path = prepare_test().await;
+    let options = Options::default().max_memtable_size(10);
+    let db = BaumDb::open_with_options(&path, options).await.unwrap();
+    for i in 0..10 {
+        db.put(format!("key-{i:04}"), "value").await.unwrap();
+    }
+    assert!(has_l0_bundle(&path));
+    let default = db.default_column_family();
+    assert_eq!(db.memtable_size(&default).unwrap(), 0);
+
+    test_clean_up(&path).await;
+}